@@ -1,61 +1,28 @@
-use bevy::{math::Vec3, prelude::{Res, ResMut, Transform}};
 use binance::api::*;
 use binance::market::*;
 
-#[derive(Default)]
-pub struct BinanceMarket {
-    market: Option<Market>,
-}
-
-#[derive(Default)]
-pub struct HotPrice {
-    pub last: f64,
-    pub actual: f64,
-}
+use super::feed::PriceFeed;
 
-pub fn setup_binance(mut binance_market: ResMut<BinanceMarket>) {
-    let market: Market = Binance::new(None, None);
-    binance_market.market = Some(market);
+/// Live prices from Binance's REST API
+pub struct BinanceFeed {
+    market: Market,
 }
 
-pub fn update_price(hot_price: Res<HotPrice>) {
-    //
+impl Default for BinanceFeed {
+    fn default() -> Self {
+        BinanceFeed { market: Binance::new(None, None) }
+    }
 }
 
-pub fn get_price(
-    mut hot_price: ResMut<HotPrice>,
-    binance_market: Res<BinanceMarket>, /*, ticker_pair: String */    
-) {
-    // Latest price for ONE symbol
-
-    
-        /* let mut rng = rand::thread_rng();
-        let start_time = Instant::now();
-        let duration = Duration::from_secs_f32(rng.gen_range(0.05..0.2));
-        while Instant::now() - start_time < duration {
-            // Spinning for 'duration', simulating doing hard
-            // compute work generating translation coords!
-        }
-        // Such hard work, all done!
-        Transform::from_translation(Vec3::new(x as f32, y as f32, z as f32)) */
-    
-    
-    // Spawn new entity and add our new task as a component
-    // commands.spawn().insert(task);
-
-    if let Some(answ) = &binance_market.market {        
-        let price = answ.get_price("HOTUSDT").unwrap().price;
-        hot_price.last = hot_price.actual;
-        hot_price.actual = price;
-        println!("binance_api::get_price(): price: {:?}", price);        
-    } else {
-        println!("binance_api::get_price(): error");
+impl PriceFeed for BinanceFeed {
+    fn latest(&mut self, symbol: &str) -> Result<f64, String> {
+        self.market.get_price(symbol).map(|answer| answer.price).map_err(|e| e.to_string())
     }
 
-    /* match answ {
-        Ok(answer) => println!("{:?}", answer),
-        Err(e) => println!("Error: {:?}", e),
-    } */
+    fn change_24h(&mut self, symbol: &str) -> Result<Option<f64>, String> {
+        let stats = self.market.get_24h_price_stats(symbol).map_err(|e| e.to_string())?;
+        Ok(stats.price_change_percent.parse().ok())
+    }
 }
 
 /*
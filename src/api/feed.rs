@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{ Arc, Mutex, TryLockError };
+use std::sync::mpsc::{ self, Receiver, TryRecvError };
+use std::thread;
+
+use bevy::prelude::{ Res, ResMut, State, Time, Timer, info, warn };
+use bevy::ecs::schedule::ShouldRun;
+use rand::{ Rng, SeedableRng, rngs::StdRng };
+
+use crate::config::market::{ FeedBackend, MarketConfig };
+use crate::game::GameState;
+use super::binance::BinanceFeed;
+use super::stream::TickStream;
+use super::watch::MarketWatch;
+
+/// Seconds between two price polls
+const MARKET_POLL_INTERVAL: f32 = 5.0;
+/// Seconds a request may take before it counts as failed
+const FETCH_TIMEOUT: f64 = 10.0;
+/// The longest the backoff waits between two attempts, in seconds
+const MAX_BACKOFF: f64 = 120.0;
+
+/// Somewhere to get prices from. `latest` may block on the network, so it's only called
+/// from a request thread.
+pub trait PriceFeed: Send {
+    fn latest(&mut self, symbol: &str) -> Result<f64, String>;
+
+    /// How far the price moved over the last 24 hours, in percent, if the feed knows
+    fn change_24h(&mut self, _symbol: &str) -> Result<Option<f64>, String> {
+        Ok(None)
+    }
+}
+
+/// Prices fetched in one go: symbol, price and 24h change
+type Fetched = Vec<(String, f64, Option<f64>)>;
+
+/// Made up prices for playing offline: every symbol wanders off `start` on its own.
+/// The day started at `start`, as far as the 24h change goes.
+pub struct RandomWalkFeed {
+    rng: StdRng,
+    start: f64,
+    volatility: f64,
+    prices: HashMap<String, f64>,
+}
+
+impl RandomWalkFeed {
+    pub fn new(seed: u64, start: f64, volatility: f64) -> Self {
+        RandomWalkFeed { rng: StdRng::seed_from_u64(seed), start, volatility, prices: HashMap::new() }
+    }
+}
+
+impl PriceFeed for RandomWalkFeed {
+    fn latest(&mut self, symbol: &str) -> Result<f64, String> {
+        let step = if self.volatility > 0.0 { self.rng.gen_range(-self.volatility..self.volatility) } else { 0.0 };
+        let price = self.prices.entry(symbol.to_string()).or_insert(self.start);
+        // never quite down to nothing
+        *price = (*price * (1.0 + step)).max(f64::EPSILON);
+        Ok(*price)
+    }
+
+    fn change_24h(&mut self, symbol: &str) -> Result<Option<f64>, String> {
+        Ok(self.prices.get(symbol).map(|price| (price / self.start - 1.0) * 100.0))
+    }
+}
+
+/// Historical ticks, one `time,symbol,price` line each, played back in order and over again
+/// once they run out. The 24h change counts from the first tick of the file.
+#[derive(Default)]
+pub struct CsvFeed {
+    ticks: HashMap<String, Vec<f64>>,
+    played: HashMap<String, usize>,
+}
+
+impl CsvFeed {
+    /// Reads the ticks, skipping blank lines, `#` comments and a header line
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut feed = CsvFeed::default();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let price = match fields.as_slice() {
+                [_, _, price] => price.parse::<f64>(),
+                _ => return Err(format!("line {}: expected time,symbol,price", index + 1)),
+            };
+            match price {
+                Ok(price) => feed.ticks.entry(fields[1].to_string()).or_default().push(price),
+                Err(_) if index == 0 => continue,
+                Err(e) => return Err(format!("line {}: {}", index + 1, e)),
+            }
+        }
+        Ok(feed)
+    }
+
+    pub fn open(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        CsvFeed::parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+}
+
+impl PriceFeed for CsvFeed {
+    fn latest(&mut self, symbol: &str) -> Result<f64, String> {
+        let ticks = self.ticks.get(symbol).filter(|ticks| !ticks.is_empty()).ok_or_else(|| format!("no ticks for {}", symbol))?;
+        let played = self.played.entry(symbol.to_string()).or_insert(0);
+        let price = ticks[*played % ticks.len()];
+        *played += 1;
+        Ok(price)
+    }
+
+    fn change_24h(&mut self, symbol: &str) -> Result<Option<f64>, String> {
+        let ticks = match self.ticks.get(symbol) {
+            Some(ticks) if !ticks.is_empty() => ticks,
+            _ => return Ok(None),
+        };
+        let played = self.played.get(symbol).copied().unwrap_or(0);
+        let last = ticks[(played + ticks.len() - 1) % ticks.len()];
+        Ok(Some((last / ticks[0] - 1.0) * 100.0))
+    }
+}
+
+/// Asks `feed` for the prices on a thread of its own, so a request stuck on the network
+/// holds up nothing but itself
+fn request_prices(feed: Arc<Mutex<Box<dyn PriceFeed>>>, symbols: Vec<String>) -> Receiver<Result<Fetched, String>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let result = match feed.try_lock() {
+            Ok(mut feed) => fetch_all(feed.as_mut(), &symbols),
+            // a request that timed out is still waiting on the network
+            Err(TryLockError::WouldBlock) => Err(String::from("the price feed is busy")),
+            Err(TryLockError::Poisoned(_)) => Err(String::from("the price feed broke down")),
+        };
+        // nobody's waiting for it any more if the request timed out
+        let _ = sender.send(result);
+    });
+    receiver
+}
+
+/// The price and 24h change of every symbol the feed has. Fails only if it has none of them.
+pub fn fetch_all(feed: &mut dyn PriceFeed, symbols: &[String]) -> Result<Fetched, String> {
+    let mut fetched = Vec::new();
+    let mut error = None;
+    for symbol in symbols {
+        match feed.latest(symbol) {
+            Ok(price) => {
+                // a price without its change is still worth having
+                let change_24h = feed.change_24h(symbol).unwrap_or(None);
+                fetched.push((symbol.clone(), price, change_24h));
+            }
+            Err(e) => {
+                error.get_or_insert(format!("{}: {}", symbol, e));
+            }
+        }
+    }
+    match error {
+        Some(e) if fetched.is_empty() => Err(e),
+        _ => Ok(fetched),
+    }
+}
+
+pub fn open_feed(backend: &FeedBackend) -> Result<Box<dyn PriceFeed>, String> {
+    let feed: Box<dyn PriceFeed> = match backend {
+        FeedBackend::Binance => Box::new(BinanceFeed::default()),
+        FeedBackend::RandomWalk { seed, start, volatility } => Box::new(RandomWalkFeed::new(*seed, *start, *volatility)),
+        FeedBackend::Csv { path } => Box::new(CsvFeed::open(path)?),
+    };
+    Ok(feed)
+}
+
+/// The feed picked in `MarketConfig`, shared with the request thread
+#[derive(Default)]
+pub struct MarketFeed(pub Option<Arc<Mutex<Box<dyn PriceFeed>>>>);
+
+/// Only runs down while playing, so a paused game doesn't keep hitting the API
+pub struct MarketTimer(pub Timer);
+
+impl Default for MarketTimer {
+    fn default() -> Self {
+        MarketTimer(Timer::from_seconds(MARKET_POLL_INTERVAL, true))
+    }
+}
+
+/// How the price feed is doing, for the UI to show
+#[derive(Clone, Debug, PartialEq)]
+pub enum MarketStatus {
+    /// No price came in yet
+    Waiting,
+    Live,
+    /// The last attempts went wrong, the next one is due `retry_at` seconds after startup
+    Failing { error: String, retry_at: f64 },
+}
+
+impl Default for MarketStatus {
+    fn default() -> Self {
+        MarketStatus::Waiting
+    }
+}
+
+/// The request in flight, if any, and how the last ones went
+#[derive(Default)]
+pub struct PriceFetch {
+    /// Where the answer to the request turns up, and when it was sent
+    request: Option<(Mutex<Receiver<Result<Fetched, String>>>, f64)>,
+    failures: u32,
+    pub status: MarketStatus,
+}
+
+impl PriceFetch {
+    fn succeed(&mut self) {
+        self.failures = 0;
+        self.status = MarketStatus::Live;
+    }
+
+    fn fail(&mut self, error: String, now: f64) {
+        self.failures += 1;
+        let wait = backoff(self.failures);
+        warn!("price_fetch: {}, retrying in {:.0}s", error, wait);
+        self.status = MarketStatus::Failing { error, retry_at: now + wait };
+    }
+
+    fn backing_off(&self, now: f64) -> bool {
+        matches!(self.status, MarketStatus::Failing { retry_at, .. } if retry_at > now)
+    }
+}
+
+/// Seconds to hold off after `failures` attempts in a row went wrong: from the poll interval,
+/// doubling every time, up to `MAX_BACKOFF`
+pub fn backoff(failures: u32) -> f64 {
+    let doublings = failures.saturating_sub(1).min(16) as i32;
+    (MARKET_POLL_INTERVAL as f64 * 2f64.powi(doublings)).min(MAX_BACKOFF)
+}
+
+pub fn market_timer_finished(time: Res<Time>, state: Res<State<GameState>>, mut timer: ResMut<MarketTimer>) -> ShouldRun {
+    if *state.current() == GameState::Playing && timer.0.tick(time.delta()).just_finished() {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+pub fn setup_price_feed(config: Res<MarketConfig>, mut feed: ResMut<MarketFeed>) {
+    match open_feed(&config.backend) {
+        Ok(opened) => {
+            info!("setup_price_feed: {} from {:?}", config.symbol, config.backend);
+            feed.0 = Some(Arc::new(Mutex::new(opened)));
+        }
+        Err(e) => warn!("setup_price_feed: {}", e),
+    }
+}
+
+// ask for the latest prices in the background, unless a request is still out, the feed is backing off
+// or the prices are streaming in anyway
+pub fn start_price_fetch(
+    time: Res<Time>,
+    config: Res<MarketConfig>,
+    feed: Res<MarketFeed>,
+    stream: Res<TickStream>,
+    mut fetch: ResMut<PriceFetch>,
+) {
+    let now = time.seconds_since_startup();
+    // the stream brings fresher prices while it's up
+    if stream.connected || fetch.request.is_some() || fetch.backing_off(now) {
+        return;
+    }
+    let feed = match &feed.0 {
+        Some(feed) => feed.clone(),
+        None => return fetch.fail(String::from("no price feed"), now),
+    };
+    let answer = request_prices(feed, config.watched());
+    fetch.request = Some((Mutex::new(answer), now));
+}
+
+// pick up the prices once they're in, or give up on the request after a while
+pub fn poll_price_fetch(time: Res<Time>, mut fetch: ResMut<PriceFetch>, mut watch: ResMut<MarketWatch>) {
+    let now = time.seconds_since_startup();
+    let (result, started) = match fetch.request.as_ref() {
+        Some((answer, started)) => (answer.lock().unwrap().try_recv(), *started),
+        None => return,
+    };
+    match result {
+        Ok(Ok(fetched)) => {
+            fetch.request = None;
+            fetch.succeed();
+            for (symbol, price, change_24h) in fetched {
+                watch.update(&symbol, price, change_24h);
+            }
+        }
+        Ok(Err(e)) => {
+            fetch.request = None;
+            fetch.fail(e, now);
+        }
+        Err(TryRecvError::Disconnected) => {
+            fetch.request = None;
+            fetch.fail(String::from("the request went away without an answer"), now);
+        }
+        // there's no calling the request back, its thread is left to finish on its own
+        Err(TryRecvError::Empty) if now - started > FETCH_TIMEOUT => {
+            fetch.request = None;
+            fetch.fail(String::from("timed out"), now);
+        }
+        Err(TryRecvError::Empty) => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_a_limit() {
+        assert_eq!(5.0, backoff(1));
+        assert_eq!(10.0, backoff(2));
+        assert_eq!(40.0, backoff(4));
+        assert_eq!(MAX_BACKOFF, backoff(10));
+        assert_eq!(MAX_BACKOFF, backoff(u32::MAX));
+
+        let mut fetch = PriceFetch::default();
+        fetch.fail(String::from("timed out"), 100.0);
+        assert!(fetch.backing_off(104.0));
+        assert!(!fetch.backing_off(105.5));
+        fetch.succeed();
+        assert_eq!(MarketStatus::Live, fetch.status);
+    }
+
+    #[test]
+    fn busy_feed_is_not_waited_for() {
+        let feed: Arc<Mutex<Box<dyn PriceFeed>>> = Arc::new(Mutex::new(Box::new(RandomWalkFeed::new(7, 1.0, 0.05))));
+        let symbols = vec![String::from("HOTUSDT")];
+
+        let held = feed.lock().unwrap();
+        let answer = request_prices(feed.clone(), symbols.clone());
+        assert_eq!(Err(String::from("the price feed is busy")), answer.recv().unwrap());
+        drop(held);
+
+        let fetched = request_prices(feed, symbols).recv().unwrap().unwrap();
+        assert_eq!("HOTUSDT", fetched[0].0);
+    }
+
+    #[test]
+    fn random_walk_is_repeatable() {
+        let walk = |seed: u64| {
+            let mut feed = RandomWalkFeed::new(seed, 1.0, 0.05);
+            (0..50).map(|_| feed.latest("HOTUSDT").unwrap()).collect::<Vec<f64>>()
+        };
+        let prices = walk(7);
+        assert_eq!(prices, walk(7));
+        assert_ne!(prices, walk(8));
+        assert!(prices.iter().all(|price| *price > 0.0));
+        // every step stays within the volatility
+        for pair in prices.windows(2) {
+            assert!((pair[1] / pair[0] - 1.0).abs() <= 0.05 + 1e-9);
+        }
+    }
+
+    #[test]
+    fn csv_ticks_play_back_per_symbol() {
+        let mut feed = CsvFeed::parse("time,symbol,price\n1,HOTUSDT,0.5\n# a gap\n\n2,BTCUSDT,40000\n3,HOTUSDT,0.75\n").unwrap();
+        assert_eq!(Ok(0.5), feed.latest("HOTUSDT"));
+        assert_eq!(Ok(40000.0), feed.latest("BTCUSDT"));
+        assert_eq!(Ok(0.75), feed.latest("HOTUSDT"));
+        assert_eq!(Ok(Some(50.0)), feed.change_24h("HOTUSDT"));
+        // and round again
+        assert_eq!(Ok(0.5), feed.latest("HOTUSDT"));
+        assert!(feed.latest("ETHUSDT").is_err());
+
+        assert!(CsvFeed::parse("1,HOTUSDT,0.5\n2,HOTUSDT,cheap\n").is_err());
+        assert!(CsvFeed::parse("1,HOTUSDT\n").is_err());
+    }
+
+    #[test]
+    fn fetching_skips_what_the_feed_lacks() {
+        let mut feed = CsvFeed::parse("1,HOTUSDT,0.5\n").unwrap();
+        let symbols = [String::from("HOTUSDT"), String::from("BTCUSDT")];
+        assert_eq!(Ok(vec![(String::from("HOTUSDT"), 0.5, Some(0.0))]), fetch_all(&mut feed, &symbols));
+        assert!(fetch_all(&mut feed, &symbols[1..]).is_err());
+    }
+}
@@ -1,2 +1,5 @@
 pub mod binance;
+pub mod feed;
 pub mod holochain;
+pub mod stream;
+pub mod watch;
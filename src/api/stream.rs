@@ -0,0 +1,296 @@
+use std::collections::{ HashMap, VecDeque };
+use std::io;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::mpsc::{ self, Receiver, Sender };
+use std::sync::{ Arc, Mutex };
+use std::thread;
+use std::time::Duration;
+
+use bevy::prelude::{ Res, ResMut, info, warn };
+use serde_json::Value;
+use tungstenite::Message;
+use tungstenite::client::AutoStream;
+use tungstenite::stream::Stream;
+
+use crate::config::market::MarketConfig;
+use super::watch::MarketWatch;
+
+/// How long to wait before reconnecting the first time. Doubles with every failed attempt.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+/// How long a read waits for a message before the thread checks whether it should stop
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// One trade, or the last price of a ticker update
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tick {
+    pub symbol: String,
+    pub price: f64,
+    /// Milliseconds since the epoch, as the exchange stamped it
+    pub time: u64,
+    /// In percent, only ticker updates have it
+    pub change_24h: Option<f64>,
+}
+
+/// What the stream thread reports back
+#[derive(Clone, Debug, PartialEq)]
+pub enum StreamEvent {
+    Connected,
+    Tick(Tick),
+    Disconnected(String),
+}
+
+/// Binance's combined trade and ticker stream for `symbols`
+pub fn binance_stream_url(symbols: &[String]) -> String {
+    let streams: Vec<String> = symbols
+        .iter()
+        .flat_map(|symbol| {
+            let symbol = symbol.to_lowercase();
+            vec![format!("{}@trade", symbol), format!("{}@ticker", symbol)]
+        })
+        .collect();
+    format!("wss://stream.binance.com:9443/stream?streams={}", streams.join("/"))
+}
+
+/// Reads a trade or 24h ticker message, bare or wrapped the way combined streams send them
+pub fn parse_message(text: &str) -> Option<Tick> {
+    let value: Value = serde_json::from_str(text).ok()?;
+    let data = value.get("data").unwrap_or(&value);
+    let (price, time) = match data.get("e")?.as_str()? {
+        "trade" => ("p", "T"),
+        "24hrTicker" => ("c", "E"),
+        _ => return None,
+    };
+    Some(Tick {
+        symbol: data.get("s")?.as_str()?.to_string(),
+        // prices come as strings, so they don't lose digits on the way
+        price: data.get(price)?.as_str()?.parse().ok()?,
+        time: data.get(time)?.as_u64()?,
+        change_24h: data.get("P").and_then(|change| change.as_str()).and_then(|change| change.parse().ok()),
+    })
+}
+
+/// Lets reads on `stream` give up after `timeout`, behind TLS or not
+fn set_read_timeout(stream: &AutoStream, timeout: Duration) -> io::Result<()> {
+    let tcp = match stream {
+        Stream::Plain(tcp) => tcp,
+        Stream::Tls(tls) => tls.get_ref(),
+    };
+    tcp.set_read_timeout(Some(timeout))
+}
+
+/// Connects to `url` and keeps forwarding ticks until `stop` is set or nobody listens any more,
+/// reconnecting with a growing delay whenever the connection drops
+pub fn spawn_stream(url: String, events: Sender<StreamEvent>, stop: Arc<AtomicBool>, first_delay: Duration) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut delay = first_delay;
+        while !stop.load(Ordering::Relaxed) {
+            let reason = match tungstenite::connect(url.as_str()) {
+                Ok((mut socket, _)) => {
+                    delay = first_delay;
+                    if let Err(e) = set_read_timeout(socket.get_ref(), READ_TIMEOUT) {
+                        warn!("spawn_stream: no read timeout, {}", e);
+                    }
+                    if events.send(StreamEvent::Connected).is_err() {
+                        return;
+                    }
+                    loop {
+                        match socket.read_message() {
+                            Ok(Message::Text(text)) => {
+                                if let Some(tick) = parse_message(&text) {
+                                    if events.send(StreamEvent::Tick(tick)).is_err() {
+                                        return;
+                                    }
+                                }
+                            }
+                            Ok(Message::Close(_)) => break String::from("closed by the server"),
+                            // tungstenite answers pings on its own
+                            Ok(_) => (),
+                            // nothing came in for a while, a chance to look at `stop`
+                            Err(tungstenite::Error::Io(e)) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => (),
+                            Err(e) => break e.to_string(),
+                        }
+                        if stop.load(Ordering::Relaxed) {
+                            return;
+                        }
+                    }
+                }
+                Err(e) => e.to_string(),
+            };
+            if events.send(StreamEvent::Disconnected(reason)).is_err() {
+                return;
+            }
+            thread::sleep(delay);
+            // a connection that works puts it back to `first_delay`
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+    })
+}
+
+/// The last ticks of every streamed symbol, oldest first
+pub struct TickWindows {
+    pub capacity: usize,
+    pub ticks: HashMap<String, VecDeque<Tick>>,
+}
+
+impl TickWindows {
+    pub fn new(capacity: usize) -> Self {
+        TickWindows { capacity, ticks: HashMap::new() }
+    }
+
+    pub fn push(&mut self, tick: Tick) {
+        let window = self.ticks.entry(tick.symbol.clone()).or_default();
+        window.push_back(tick);
+        while window.len() > self.capacity {
+            window.pop_front();
+        }
+    }
+
+    pub fn latest(&self, symbol: &str) -> Option<&Tick> {
+        self.ticks.get(symbol).and_then(|window| window.back())
+    }
+}
+
+impl Default for TickWindows {
+    fn default() -> Self {
+        TickWindows::new(MarketConfig::default().tick_window)
+    }
+}
+
+/// The streaming connection, when `MarketConfig::streaming` is on
+#[derive(Default)]
+pub struct TickStream {
+    events: Option<Mutex<Receiver<StreamEvent>>>,
+    stop: Arc<AtomicBool>,
+    pub connected: bool,
+}
+
+impl Drop for TickStream {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+pub fn start_tick_stream(config: Res<MarketConfig>, mut stream: ResMut<TickStream>, mut windows: ResMut<TickWindows>) {
+    *windows = TickWindows::new(config.tick_window);
+    if !config.streaming {
+        return;
+    }
+    let url = config.stream_url.clone().unwrap_or_else(|| binance_stream_url(&config.watched()));
+    info!("start_tick_stream: {}", url);
+    let (sender, receiver) = mpsc::channel();
+    spawn_stream(url, sender, stream.stop.clone(), RECONNECT_DELAY);
+    stream.events = Some(Mutex::new(receiver));
+}
+
+// move whatever came in over the stream into the tick windows and the market watch
+pub fn drain_tick_stream(mut stream: ResMut<TickStream>, mut windows: ResMut<TickWindows>, mut watch: ResMut<MarketWatch>) {
+    let events: Vec<StreamEvent> = match stream.events.as_ref().and_then(|events| events.lock().ok()) {
+        Some(events) => events.try_iter().collect(),
+        None => return,
+    };
+    for event in events {
+        match event {
+            StreamEvent::Connected => {
+                info!("drain_tick_stream: connected");
+                stream.connected = true;
+            }
+            StreamEvent::Disconnected(reason) => {
+                warn!("drain_tick_stream: disconnected, {}", reason);
+                stream.connected = false;
+            }
+            StreamEvent::Tick(tick) => {
+                watch.update(&tick.symbol, tick.price, tick.change_24h);
+                windows.push(tick);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::mpsc::RecvTimeoutError;
+
+    const TRADE: &str = r#"{"stream":"hotusdt@trade","data":{"e":"trade","E":1,"s":"HOTUSDT","t":7,"p":"0.01250000","q":"100","T":1620000000000}}"#;
+    const TICKER: &str = r#"{"e":"24hrTicker","E":1620000000500,"s":"HOTUSDT","c":"0.01300000","P":"4.0"}"#;
+
+    #[test]
+    fn reads_trades_and_tickers() {
+        assert_eq!(
+            Some(Tick { symbol: String::from("HOTUSDT"), price: 0.0125, time: 1620000000000, change_24h: None }),
+            parse_message(TRADE),
+        );
+        assert_eq!(Some((0.013, Some(4.0))), parse_message(TICKER).map(|tick| (tick.price, tick.change_24h)));
+        assert_eq!(None, parse_message(r#"{"result":null,"id":1}"#));
+        assert_eq!(None, parse_message("not json"));
+    }
+
+    #[test]
+    fn windows_keep_the_latest_ticks() {
+        let mut windows = TickWindows::new(3);
+        for time in 0..5 {
+            windows.push(Tick { symbol: String::from("HOTUSDT"), price: time as f64, time, change_24h: None });
+        }
+        windows.push(Tick { symbol: String::from("BTCUSDT"), price: 40000.0, time: 5, change_24h: None });
+        let times: Vec<u64> = windows.ticks["HOTUSDT"].iter().map(|tick| tick.time).collect();
+        assert_eq!(vec![2, 3, 4], times);
+        assert_eq!(Some(40000.0), windows.latest("BTCUSDT").map(|tick| tick.price));
+    }
+
+    #[test]
+    fn reconnects_to_a_stub_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        // one message per connection, then hang up
+        let server = thread::spawn(move || {
+            for message in [TRADE, TICKER].iter() {
+                let (tcp, _) = listener.accept().unwrap();
+                let mut socket = tungstenite::accept(tcp).unwrap();
+                socket.write_message(Message::Text(message.to_string())).unwrap();
+                socket.close(None).unwrap();
+                // let the close go through before dropping the connection
+                while socket.read_message().is_ok() {}
+            }
+        });
+
+        let (sender, receiver) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        spawn_stream(url, sender, stop.clone(), Duration::from_millis(10));
+        let mut prices = Vec::new();
+        let mut connections = 0;
+        while prices.len() < 2 {
+            match receiver.recv_timeout(Duration::from_secs(5)).expect("the stream went quiet") {
+                StreamEvent::Connected => connections += 1,
+                StreamEvent::Tick(tick) => prices.push(tick.price),
+                StreamEvent::Disconnected(_) => (),
+            }
+        }
+        stop.store(true, Ordering::Relaxed);
+        server.join().unwrap();
+        assert_eq!(vec![0.0125, 0.013], prices);
+        assert_eq!(2, connections);
+    }
+
+    #[test]
+    fn stops_on_a_quiet_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        // never says a word, just waits for the client to hang up
+        let server = thread::spawn(move || {
+            let (tcp, _) = listener.accept().unwrap();
+            let mut socket = tungstenite::accept(tcp).unwrap();
+            while socket.read_message().is_ok() {}
+        });
+
+        let (sender, receiver) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        spawn_stream(url, sender, stop.clone(), Duration::from_millis(10));
+        assert_eq!(Ok(StreamEvent::Connected), receiver.recv_timeout(Duration::from_secs(5)));
+        stop.store(true, Ordering::Relaxed);
+        // the thread is gone once its sender is
+        assert_eq!(Err(RecvTimeoutError::Disconnected), receiver.recv_timeout(Duration::from_secs(5)));
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::{ HashMap, VecDeque };
+
+use bevy::prelude::{ Res, ResMut };
+
+use crate::config::market::MarketConfig;
+
+/// How many prices a sparkline shows
+const SPARKLINE_LENGTH: usize = 24;
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// What the watch knows about one symbol
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Quote {
+    pub price: f64,
+    /// In percent, if the feed knows
+    pub change_24h: Option<f64>,
+    /// The latest prices, oldest first
+    pub history: VecDeque<f64>,
+}
+
+/// The prices of every symbol in `MarketConfig`, for the UI and for gameplay to read by name
+pub struct MarketWatch {
+    /// In the order they're listed
+    pub symbols: Vec<String>,
+    quotes: HashMap<String, Quote>,
+}
+
+impl MarketWatch {
+    pub fn new(symbols: &[String]) -> Self {
+        MarketWatch { symbols: symbols.to_vec(), quotes: HashMap::new() }
+    }
+
+    /// Only for watched symbols, and only once a price came in
+    pub fn quote(&self, symbol: &str) -> Option<&Quote> {
+        self.quotes.get(symbol)
+    }
+
+    pub fn price(&self, symbol: &str) -> Option<f64> {
+        self.quote(symbol).map(|quote| quote.price)
+    }
+
+    /// The price before the latest one
+    pub fn previous(&self, symbol: &str) -> Option<f64> {
+        let history = &self.quote(symbol)?.history;
+        history.iter().rev().nth(1).copied()
+    }
+
+    /// Take in a new price. A missing 24h change keeps the last one known.
+    pub fn update(&mut self, symbol: &str, price: f64, change_24h: Option<f64>) {
+        if !self.symbols.iter().any(|watched| watched == symbol) {
+            return;
+        }
+        let quote = self.quotes.entry(symbol.to_string()).or_default();
+        quote.price = price;
+        if change_24h.is_some() {
+            quote.change_24h = change_24h;
+        }
+        quote.history.push_back(price);
+        while quote.history.len() > SPARKLINE_LENGTH {
+            quote.history.pop_front();
+        }
+    }
+}
+
+impl Default for MarketWatch {
+    fn default() -> Self {
+        MarketWatch::new(&MarketConfig::default().watched())
+    }
+}
+
+/// The prices as a row of bars, from the lowest to the highest
+pub fn sparkline(prices: &VecDeque<f64>) -> String {
+    let low = prices.iter().copied().fold(f64::INFINITY, f64::min);
+    let high = prices.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    prices
+        .iter()
+        .map(|price| {
+            if high > low {
+                SPARKS[((price - low) / (high - low) * (SPARKS.len() - 1) as f64).round() as usize]
+            } else {
+                SPARKS[SPARKS.len() / 2]
+            }
+        })
+        .collect()
+}
+
+/// Enough digits to see cheap coins move, and no more for expensive ones
+pub fn format_price(price: f64) -> String {
+    if price < 1.0 {
+        format!("{:.5}", price)
+    } else {
+        format!("{:.2}", price)
+    }
+}
+
+pub fn setup_market_watch(config: Res<MarketConfig>, mut watch: ResMut<MarketWatch>) {
+    *watch = MarketWatch::new(&config.watched());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watch_keeps_a_short_history_per_symbol() {
+        let mut watch = MarketWatch::new(&[String::from("HOTUSDT"), String::from("BTCUSDT")]);
+        assert_eq!(None, watch.price("HOTUSDT"));
+        for step in 0..30 {
+            watch.update("HOTUSDT", step as f64, if step == 0 { Some(4.0) } else { None });
+        }
+        watch.update("DOGEUSDT", 0.3, None);
+
+        let quote = watch.quote("HOTUSDT").unwrap();
+        assert_eq!(29.0, quote.price);
+        assert_eq!(Some(4.0), quote.change_24h);
+        assert_eq!(SPARKLINE_LENGTH, quote.history.len());
+        assert_eq!(Some(28.0), watch.previous("HOTUSDT"));
+        assert_eq!(None, watch.quote("BTCUSDT"));
+        assert_eq!(None, watch.quote("DOGEUSDT"));
+    }
+
+    #[test]
+    fn sparkline_spans_low_to_high() {
+        let prices: VecDeque<f64> = vec![1.0, 2.0, 3.0, 8.0].into_iter().collect();
+        assert_eq!("▁▂▃█", sparkline(&prices));
+        let flat: VecDeque<f64> = vec![5.0, 5.0].into_iter().collect();
+        assert_eq!("▅▅", sparkline(&flat));
+        assert_eq!("", sparkline(&VecDeque::new()));
+    }
+}
@@ -12,10 +12,18 @@ pub const MESH_PATHS: [&str; 5] = [
     // "models/AlienCake/alien.glb#Scene0/Mesh0"
 ];
 
+pub const BUILDING_PATHS: [&str; 2] = [
+    "models/buildings/house.glb#Scene0",
+    "models/buildings/pizzashop/pizzashop.glb#Scene0",
+];
+
+pub const RESOURCE_PATHS: [&str; 1] = ["models/resources/apple.gltf#Scene0"];
+
 pub const FONT_PATHS: [&str; 2] = ["fonts/FiraSans-Bold.ttf", "fonts/FiraSans-Bold.ttf"];
 
 use super::game::components::{ TileType };
 use super::game::player::{ CharacterType };
+use super::game::economy::{ BuildingType, ResourceKind };
 use super::ui::{ FontType };
 use crate::MeshMonkey;
 use crate::game::GameState;
@@ -33,7 +41,9 @@ pub struct AssetIndex {
     pub tile_by_type: HashMap<TileType, Handle<Mesh>>,
     pub scene_by_type: HashMap<TileType, Handle<Scene>>,
     pub font_by_type: HashMap<FontType, Handle<Font>>,
-    pub mesh_by_type: HashMap<CharacterType, Handle<Mesh>>
+    pub mesh_by_type: HashMap<CharacterType, Handle<Mesh>>,
+    pub scene_by_building: HashMap<BuildingType, Handle<Scene>>,
+    pub scene_by_resource: HashMap<ResourceKind, Handle<Scene>>,
 }
 
 // ARCH: Where to put this? In Houby module? In resource module? WKO question / pattern dilemma is this?
@@ -76,6 +86,18 @@ pub fn load_assets(
         .insert(TileType::Cake, asset_server.load(MESH_PATHS[2]).into());
         
 
+    asset_index
+        .scene_by_building
+        .insert(BuildingType::House, asset_server.load(BUILDING_PATHS[0]).into());
+
+    asset_index
+        .scene_by_building
+        .insert(BuildingType::PizzaShop, asset_server.load(BUILDING_PATHS[1]).into());
+
+    asset_index
+        .scene_by_resource
+        .insert(ResourceKind::Apple, asset_server.load(RESOURCE_PATHS[0]).into());
+
     // Load fonts
     asset_index
         .font_by_type
@@ -90,7 +112,7 @@ pub fn check_assets_loaded (
 ) {
     if let LoadState::Loaded = asset_server.get_group_load_state(asset_index.mesh_by_type.iter().map(|(_, handle)| { handle.id })) {
         println!("Meshes loaded!");
-        state.set(GameState::FinishedLoading).unwrap();
+        state.set(GameState::MainMenu).unwrap();
     }
 
     /* if let LoadState::Loaded =
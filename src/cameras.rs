@@ -1,7 +1,11 @@
-use bevy::prelude::{ Query, Transform, Vec3, Commands, PerspectiveCameraBundle, ResMut, Res, QuerySet, Time, UiCameraBundle };
-use bevy::render::{ camera::Camera, render_graph::base::camera::{CAMERA_3D} };
+use bevy::input::mouse::{ MouseMotion, MouseScrollUnit, MouseWheel };
+use bevy::prelude::{ Query, Transform, Vec2, Vec3, Quat, Commands, PerspectiveCameraBundle, OrthographicCameraBundle, ResMut, Res, QuerySet, Time, UiCameraBundle, EventReader, EventWriter, Input, KeyCode, MouseButton, With, Entity, Local, info };
+use bevy::render::{ camera::{ Camera, OrthographicProjection, PerspectiveProjection }, render_graph::base::camera::{CAMERA_3D} };
 use crate::{ BOARD_SIZE_I, BOARD_SIZE_J };
+use crate::config::camera::CameraConfig;
 use crate::game::{ Game };
+use crate::game::ai::Npc;
+use crate::input::{ EditorMode, GameCommand, MoveDirection };
 
 use bevy_mod_picking::PickingCameraBundle;
 
@@ -10,65 +14,463 @@ const RESET_FOCUS: [f32; 3] = [
     0.0,
     BOARD_SIZE_J as f32 / 2.0 - 0.5,
 ];
+/// Where the camera starts out relative to `RESET_FOCUS`
+const CAMERA_OFFSET: [f32; 3] = [
+    -(BOARD_SIZE_I as f32),
+    2.0 * BOARD_SIZE_J as f32 / 3.0,
+    0.0,
+];
+/// The vertical field of view of the perspective camera, Bevy's default. The orthographic ones
+/// show as much of the board as it would at the same distance.
+const FOV: f32 = std::f32::consts::FRAC_PI_4;
+/// A sixth of a turn, so the hex board looks the same after every step
+const ROTATION_STEP: f32 = std::f32::consts::FRAC_PI_3;
+/// Scrolling by pixels, as touchpads do, counts this many of them as a line
+const PIXELS_PER_LINE: f32 = 50.0;
+
+/// Asks to move the camera, from the mouse, the keyboard or touch gestures
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CameraControl {
+    /// Drag the board by this many pixels on screen
+    Pan(Vec2),
+    /// Scale the distance to the board by this factor
+    Zoom(f32),
+    /// Turn around the target and tilt up or down, in radians
+    Orbit { yaw: f32, pitch: f32 },
+    /// Go back to following the player
+    SnapBack,
+    ToggleFollow,
+    /// Turn a sixth of the way around the target
+    Rotate { clockwise: bool },
+    CycleProjection,
+}
+
+/// How the board is drawn: in perspective, or flat from one of the classic tile game angles
+#[derive(Copy, Clone, Debug, PartialEq, Hash)]
+pub enum CameraProjection {
+    Perspective,
+    /// Looking down at about 35.26°, so the three axes come out equally long
+    Isometric,
+    /// Looking down at 30°, the 2:1 pixel art angle
+    Dimetric,
+}
+impl Eq for CameraProjection {}
+
+impl CameraProjection {
+    pub fn next(&self) -> CameraProjection {
+        match self {
+            CameraProjection::Perspective => CameraProjection::Isometric,
+            CameraProjection::Isometric => CameraProjection::Dimetric,
+            CameraProjection::Dimetric => CameraProjection::Perspective,
+        }
+    }
+
+    /// The fixed angle above the board, if there is one
+    pub fn pitch(&self) -> Option<f32> {
+        match self {
+            CameraProjection::Perspective => None,
+            CameraProjection::Isometric => Some((1.0 / 2f32.sqrt()).atan()),
+            CameraProjection::Dimetric => Some(std::f32::consts::FRAC_PI_6),
+        }
+    }
+
+    /// How far away the camera has to be to see all of a ball of `radius`
+    pub fn fit_distance(&self, radius: f32, aspect_ratio: f32) -> f32 {
+        match self {
+            CameraProjection::Perspective => fit_distance(radius, FOV, aspect_ratio),
+            _ => radius / aspect_ratio.min(1.0) / (FOV / 2.0).tan(),
+        }
+    }
+}
+
+/// Whether the camera stays on the player or wherever it was panned to
+#[derive(Copy, Clone, Debug, PartialEq, Hash)]
+pub enum CameraMode {
+    Follow,
+    Free,
+}
+impl Eq for CameraMode {}
+
+/// An orbit camera: it should look at `target` from `distance` away, turned by `yaw` and tilted by `pitch`.
+/// `eye` and `look_at` are where it actually is and looks, on their way there.
+pub struct CameraRig {
+    pub mode: CameraMode,
+    pub projection: CameraProjection,
+    pub target: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub distance: f32,
+    pub eye: Vec3,
+    pub look_at: Vec3,
+    pub eye_velocity: Vec3,
+    pub look_velocity: Vec3,
+}
+
+impl Default for CameraRig {
+    fn default() -> Self {
+        let offset = Vec3::from(CAMERA_OFFSET);
+        let mut rig = CameraRig {
+            mode: CameraMode::Follow,
+            projection: CameraProjection::Perspective,
+            target: Vec3::from(RESET_FOCUS),
+            yaw: 0.0,
+            pitch: offset.y.atan2(-offset.x),
+            distance: offset.length(),
+            eye: Vec3::ZERO,
+            look_at: Vec3::from(RESET_FOCUS),
+            eye_velocity: Vec3::ZERO,
+            look_velocity: Vec3::ZERO,
+        };
+        rig.eye = rig.target + rig.offset(rig.distance);
+        rig
+    }
+}
+
+impl CameraRig {
+    pub fn apply(&mut self, control: CameraControl, config: &CameraConfig) {
+        match control {
+            CameraControl::Pan(delta) => {
+                // the board follows the cursor, so the view goes the other way
+                let forward = Quat::from_rotation_y(self.yaw) * Vec3::X;
+                let right = forward.cross(Vec3::Y);
+                let scale = config.pan_speed * self.distance / Vec3::from(CAMERA_OFFSET).length();
+                self.target -= (right * delta.x + forward * delta.y) * scale;
+                self.mode = CameraMode::Free;
+            }
+            CameraControl::Zoom(factor) => {
+                self.distance = (self.distance * factor).clamp(config.min_distance, config.max_distance);
+            }
+            CameraControl::Orbit { yaw, pitch } => {
+                self.yaw += yaw;
+                self.pitch = (self.pitch + pitch).clamp(config.min_pitch, config.max_pitch);
+            }
+            CameraControl::SnapBack => self.mode = CameraMode::Follow,
+            CameraControl::ToggleFollow => {
+                self.mode = match self.mode {
+                    CameraMode::Follow => CameraMode::Free,
+                    CameraMode::Free => CameraMode::Follow,
+                };
+            }
+            CameraControl::Rotate { clockwise } => {
+                // start from the step the camera is showing, not from wherever orbiting left it
+                let step = (self.yaw / ROTATION_STEP).round();
+                self.yaw = (step + if clockwise { -1.0 } else { 1.0 }) * ROTATION_STEP;
+            }
+            CameraControl::CycleProjection => self.projection = self.projection.next(),
+        }
+    }
+
+    /// The angles the camera looks from. The flat projections keep their own pitch and turn
+    /// in whole steps only.
+    pub fn view_angles(&self) -> (f32, f32) {
+        match self.projection.pitch() {
+            Some(pitch) => ((self.yaw / ROTATION_STEP).round() * ROTATION_STEP, pitch),
+            None => (self.yaw, self.pitch),
+        }
+    }
+
+    /// From the target to the camera, `distance` long
+    pub fn offset(&self, distance: f32) -> Vec3 {
+        let (yaw, pitch) = self.view_angles();
+        Quat::from_rotation_y(yaw) * Vec3::new(-pitch.cos(), pitch.sin(), 0.0) * distance
+    }
+}
 
-pub fn setup_cameras(mut commands: Commands, mut game: ResMut<Game>) {
-    game.camera_should_focus = Vec3::from(RESET_FOCUS);
-    game.camera_is_focus = game.camera_should_focus;
-    commands.spawn_bundle(PerspectiveCameraBundle {
-        transform: Transform::from_xyz(
-            -(BOARD_SIZE_I as f32 / 2.0),
-            2.0 * BOARD_SIZE_J as f32 / 3.0,
-            BOARD_SIZE_J as f32 / 2.0 - 0.5,
-        )
-        .looking_at(game.camera_is_focus, Vec3::Y),
-        ..Default::default()
-    })
+/// One step of a critically damped spring pulling `current` toward `target`: as quick as it gets
+/// without overshooting. `smooth_time` is roughly how long it takes to get there. Solved exactly
+/// rather than integrated, so it ends up in the same place at any frame rate.
+pub fn smooth_damp(current: Vec3, target: Vec3, velocity: &mut Vec3, smooth_time: f32, dt: f32) -> Vec3 {
+    if smooth_time <= 0.0 {
+        *velocity = Vec3::ZERO;
+        return target;
+    }
+    let omega = 2.0 / smooth_time;
+    let change = current - target;
+    let temp = (*velocity + change * omega) * dt;
+    let decay = (-omega * dt).exp();
+    *velocity = (*velocity - temp * omega) * decay;
+    target + (change + temp) * decay
+}
+
+/// The middle of `points` and how far out they reach from it, plus `margin`
+pub fn frame(points: &[Vec3], margin: f32) -> Option<(Vec3, f32)> {
+    let (min, max) = points.iter().skip(1).fold(
+        (*points.first()?, *points.first()?),
+        |(min, max), point| (min.min(*point), max.max(*point)),
+    );
+    let center = (min + max) / 2.0;
+    let radius = points.iter().map(|point| (*point - center).length()).fold(0.0, f32::max);
+    Some((center, radius + margin))
+}
+
+/// How far away a camera with this vertical field of view has to be to see all of a ball of `radius`
+pub fn fit_distance(radius: f32, fov: f32, aspect_ratio: f32) -> f32 {
+    let horizontal = 2.0 * ((fov / 2.0).tan() * aspect_ratio).atan();
+    radius / (fov.min(horizontal) / 2.0).sin()
+}
+
+/// The 3D camera for the rig's projection, where the rig is
+fn spawn_camera(commands: &mut Commands, rig: &CameraRig) {
+    let transform = Transform::from_translation(rig.eye).looking_at(rig.look_at, Vec3::Y);
+    let mut camera = match rig.projection {
+        CameraProjection::Perspective => commands.spawn_bundle(PerspectiveCameraBundle { transform, ..Default::default() }),
+        _ => commands.spawn_bundle(OrthographicCameraBundle { transform, ..OrthographicCameraBundle::new_3d() }),
+    };
     // Insert picking camera bundle for Picking module
-    .insert_bundle(PickingCameraBundle::default());
+    camera.insert_bundle(PickingCameraBundle::default());
+}
+
+pub fn setup_cameras(mut commands: Commands, mut game: ResMut<Game>, rig: Res<CameraRig>) {
+    game.camera_should_focus = rig.target;
+    game.camera_is_focus = rig.look_at;
+    spawn_camera(&mut commands, &rig);
 
     // commands.spawn_bundle(OrthographicCameraBundle::new_2d());
     commands.spawn_bundle(UiCameraBundle::default());
 }
 
-// change the focus of the camera
+// Right-drag orbits, middle-drag pans, the wheel zooms. The editor selects with the
+// right button, so there orbiting takes Alt as well.
+pub fn camera_mouse_input(
+    config: Res<CameraConfig>,
+    editor: Res<EditorMode>,
+    keys: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    mut motion: EventReader<MouseMotion>,
+    mut wheel: EventReader<MouseWheel>,
+    mut controls: EventWriter<CameraControl>,
+) {
+    let alt = keys.pressed(KeyCode::LAlt) || keys.pressed(KeyCode::RAlt);
+    let orbiting = mouse.pressed(MouseButton::Right) && (alt || !editor.0);
+    let panning = mouse.pressed(MouseButton::Middle);
+    for event in motion.iter() {
+        // mouse motion counts down the screen, the controls count up
+        let delta = Vec2::new(event.delta.x, -event.delta.y);
+        if orbiting {
+            controls.send(CameraControl::Orbit { yaw: -delta.x * config.orbit_speed, pitch: -delta.y * config.orbit_speed });
+        } else if panning {
+            controls.send(CameraControl::Pan(delta));
+        }
+    }
+
+    for event in wheel.iter() {
+        let lines = match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / PIXELS_PER_LINE,
+        };
+        controls.send(CameraControl::Zoom(config.zoom_step.powf(lines)));
+    }
+}
+
+/// Which way the board slides under a pan command. Panning drags the board, so it goes against the camera.
+fn pan_direction(dir: MoveDirection) -> Vec2 {
+    match dir {
+        MoveDirection::Up => Vec2::new(0.0, -1.0),
+        MoveDirection::Down => Vec2::new(0.0, 1.0),
+        MoveDirection::Left => Vec2::new(1.0, 0.0),
+        MoveDirection::Right => Vec2::new(-1.0, 0.0),
+    }
+}
+
+pub fn camera_controls(
+    time: Res<Time>,
+    config: Res<CameraConfig>,
+    mut controls: EventReader<CameraControl>,
+    mut game_commands: EventReader<GameCommand>,
+    mut rig: ResMut<CameraRig>,
+) {
+    for control in controls.iter() {
+        rig.apply(*control, &config);
+    }
+    let mut key_pan = Vec2::ZERO;
+    for command in game_commands.iter() {
+        let control = match command {
+            // held down for as long as the camera should move, so it goes by the frame time
+            GameCommand::PanCamera { dir } => {
+                key_pan += pan_direction(*dir);
+                continue;
+            }
+            GameCommand::SnapCamera => CameraControl::SnapBack,
+            GameCommand::ToggleCameraFollow => CameraControl::ToggleFollow,
+            GameCommand::RotateCamera { clockwise } => CameraControl::Rotate { clockwise: *clockwise },
+            GameCommand::CycleCameraProjection => CameraControl::CycleProjection,
+            _ => continue,
+        };
+        rig.apply(control, &config);
+        info!("camera_controls: camera {:?}, {:?}", rig.mode, rig.projection);
+    }
+    if key_pan != Vec2::ZERO {
+        rig.apply(CameraControl::Pan(key_pan * config.key_pan_speed * time.delta_seconds()), &config);
+    }
+}
+
+// Bevy keeps the projection in its own component, so switching it takes a new camera.
+// The picking camera goes along, and picks through either projection.
+pub fn switch_projection(
+    mut commands: Commands,
+    mut current: Local<Option<CameraProjection>>,
+    rig: Res<CameraRig>,
+    cameras: Query<(Entity, &Camera)>,
+) {
+    if *current == Some(rig.projection) {
+        return;
+    }
+    // the first camera comes from `setup_cameras`
+    if current.is_some() {
+        for (entity, camera) in cameras.iter() {
+            if camera.name == Some(CAMERA_3D.to_string()) {
+                commands.entity(entity).despawn();
+            }
+        }
+        spawn_camera(&mut commands, &rig);
+    }
+    *current = Some(rig.projection);
+}
+
+// Follow the player, backing off far enough to keep the opponents and the cake in view,
+// or stay wherever the camera was panned to. Where the camera is and where it looks both
+// glide there on their own springs.
 pub fn focus_camera(
     time: Res<Time>,
+    config: Res<CameraConfig>,
+    mut rig: ResMut<CameraRig>,
     mut game: ResMut<Game>,
-    mut transforms: QuerySet<(Query<(&mut Transform, &Camera)>, Query<&Transform>)>,
+    mut transforms: QuerySet<(
+        Query<(&mut Transform, &Camera, Option<&PerspectiveProjection>, Option<&mut OrthographicProjection>)>,
+        Query<&Transform>,
+        Query<&Transform, With<Npc>>,
+    )>,
 ) {
-    const SPEED: f32 = 0.1;
-    // if there is both a player and a bonus, target the mid-point of them
-    if let (Some(player_entity), Some(bonus_entity)) = (game.player.entity, game.bonus.entity) {
-        if let (Ok(player_transform), Ok(bonus_transform)) = (
-            transforms.q1().get(player_entity),
-            transforms.q1().get(bonus_entity),
-        ) {
-            game.camera_should_focus = player_transform
-                .translation
-                .lerp(bonus_transform.translation, 0.1);
+    let rig = &mut *rig;
+    let mut distance = rig.distance;
+    if rig.mode == CameraMode::Follow {
+        let player = game.player.entity.and_then(|e| transforms.q1().get(e).ok()).map(|t| t.translation);
+        let mut points: Vec<Vec3> = player.into_iter().collect();
+        if config.frame_all || player.is_none() {
+            points.extend(game.bonus.entity.and_then(|e| transforms.q1().get(e).ok()).map(|t| t.translation));
+            points.extend(transforms.q2().iter().map(|t| t.translation));
         }
-    // otherwise, if there is only a player, target the player
-    } else if let Some(player_entity) = game.player.entity {
-        if let Ok(player_transform) = transforms.q1().get(player_entity) {
-            game.camera_should_focus = player_transform.translation;
+        let (center, radius) = frame(&points, config.frame_margin).unwrap_or((Vec3::from(RESET_FOCUS), 0.0));
+        rig.target = center;
+        let aspect_ratio = transforms
+            .q0_mut()
+            .iter_mut()
+            .find(|(_, camera, _, _)| camera.name == Some(CAMERA_3D.to_string()))
+            .map(|(_, _, perspective, orthographic)| match (perspective, orthographic) {
+                (Some(perspective), _) => perspective.aspect_ratio,
+                // the fixed vertical scaling spans -1..1 up and down, and as far as it takes to the sides
+                (_, Some(orthographic)) => orthographic.right / orthographic.top,
+                _ => 1.0,
+            });
+        if let Some(aspect_ratio) = aspect_ratio {
+            distance = distance.max(rig.projection.fit_distance(radius, aspect_ratio)).min(config.max_distance);
         }
-    // otherwise, target the middle
-    } else {
-        game.camera_should_focus = Vec3::from(RESET_FOCUS);
-    }
-    // calculate the camera motion based on the difference between where the camera is looking
-    // and where it should be looking; the greater the distance, the faster the motion;
-    // smooth out the camera movement using the frame time
-    let mut camera_motion = game.camera_should_focus - game.camera_is_focus;
-    if camera_motion.length() > 0.2 {
-        camera_motion *= SPEED * time.delta_seconds();
-        // set the new camera's actual focus
-        game.camera_is_focus += camera_motion;
-    }
-    // look at that new camera's actual focus
-    for (mut transform, camera) in transforms.q0_mut().iter_mut() {
+    }
+
+    let dt = time.delta_seconds();
+    let eye = rig.target + rig.offset(distance);
+    rig.eye = smooth_damp(rig.eye, eye, &mut rig.eye_velocity, config.position_smoothing, dt);
+    rig.look_at = smooth_damp(rig.look_at, rig.target, &mut rig.look_velocity, config.target_smoothing, dt);
+    game.camera_should_focus = rig.target;
+    game.camera_is_focus = rig.look_at;
+
+    let half_height = (rig.eye - rig.look_at).length() * (FOV / 2.0).tan();
+    for (mut transform, camera, _, orthographic) in transforms.q0_mut().iter_mut() {
         if camera.name == Some(CAMERA_3D.to_string()) {
-            *transform = transform.looking_at(game.camera_is_focus, Vec3::Y);
+            *transform = Transform::from_translation(rig.eye).looking_at(rig.look_at, Vec3::Y);
+            // flat projections don't shrink with distance, so zoom by scaling them
+            if let Some(mut orthographic) = orthographic {
+                if (orthographic.scale - half_height).abs() > f32::EPSILON {
+                    orthographic.scale = half_height;
+                }
+            }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starts_where_the_fixed_camera_was() {
+        let rig = CameraRig::default();
+        let fixed = Vec3::from(RESET_FOCUS) + Vec3::from(CAMERA_OFFSET);
+        assert!((rig.eye - fixed).length() < 1e-4, "{:?} != {:?}", rig.eye, fixed);
+    }
+
+    #[test]
+    fn controls_stay_within_the_clamps() {
+        let config = CameraConfig::default();
+        let mut rig = CameraRig::default();
+        rig.apply(CameraControl::Zoom(0.01), &config);
+        assert_eq!(config.min_distance, rig.distance);
+        rig.apply(CameraControl::Zoom(100.0), &config);
+        assert_eq!(config.max_distance, rig.distance);
+        rig.apply(CameraControl::Orbit { yaw: 0.5, pitch: 10.0 }, &config);
+        assert_eq!(config.max_pitch, rig.pitch);
+        rig.apply(CameraControl::Orbit { yaw: 0.0, pitch: -10.0 }, &config);
+        assert_eq!(config.min_pitch, rig.pitch);
+
+        // panning lets go of the player until the camera snaps back
+        let target = rig.target;
+        rig.apply(CameraControl::Pan(Vec2::new(30.0, 0.0)), &config);
+        assert_eq!(CameraMode::Free, rig.mode);
+        assert_ne!(target, rig.target);
+        assert!((rig.target.y - target.y).abs() < 1e-6);
+        rig.apply(CameraControl::SnapBack, &config);
+        assert_eq!(CameraMode::Follow, rig.mode);
+    }
+
+    #[test]
+    fn flat_projections_turn_in_hex_steps() {
+        let config = CameraConfig::default();
+        let mut rig = CameraRig::default();
+        rig.apply(CameraControl::Orbit { yaw: 0.4, pitch: 0.3 }, &config);
+        assert_eq!((rig.yaw, rig.pitch), rig.view_angles());
+
+        rig.apply(CameraControl::CycleProjection, &config);
+        assert_eq!(CameraProjection::Isometric, rig.projection);
+        let (yaw, pitch) = rig.view_angles();
+        assert_eq!(0.0, yaw);
+        assert!((pitch.to_degrees() - 35.264).abs() < 1e-3);
+
+        rig.apply(CameraControl::Rotate { clockwise: false }, &config);
+        rig.apply(CameraControl::Rotate { clockwise: false }, &config);
+        assert!((rig.view_angles().0.to_degrees() - 120.0).abs() < 1e-3);
+        rig.apply(CameraControl::CycleProjection, &config);
+        assert!((rig.view_angles().1.to_degrees() - 30.0).abs() < 1e-3);
+        rig.apply(CameraControl::CycleProjection, &config);
+        assert_eq!(CameraProjection::Perspective, rig.projection);
+    }
+
+    #[test]
+    fn damping_ignores_the_frame_rate_and_never_overshoots() {
+        let target = Vec3::new(10.0, 0.0, 0.0);
+        let run = |frames: usize| {
+            let (mut position, mut velocity) = (Vec3::ZERO, Vec3::ZERO);
+            for _ in 0..frames {
+                position = smooth_damp(position, target, &mut velocity, 0.3, 1.0 / frames as f32);
+                assert!(position.x <= target.x);
+            }
+            position
+        };
+        let (slow, fast) = (run(20), run(240));
+        assert!((slow - fast).length() < 1e-3, "{:?} != {:?}", slow, fast);
+        assert!((target - fast).length() < 0.2);
+    }
+
+    #[test]
+    fn framing_backs_off_to_fit_everyone() {
+        let points = [Vec3::new(0.0, 0.0, 0.0), Vec3::new(6.0, 0.0, 8.0)];
+        let (center, radius) = frame(&points, 1.0).unwrap();
+        assert_eq!(Vec3::new(3.0, 0.0, 4.0), center);
+        assert!((radius - 6.0).abs() < 1e-5);
+        assert_eq!(None, frame(&[], 1.0));
+
+        let fov = std::f32::consts::FRAC_PI_2;
+        assert!((fit_distance(1.0, fov, 1.0) - 2f32.sqrt()).abs() < 1e-5);
+        // a narrow window needs more room
+        assert!(fit_distance(1.0, fov, 0.5) > fit_distance(1.0, fov, 1.0));
+    }
+}
@@ -0,0 +1,61 @@
+use bevy::prelude::*;
+use serde::{ Deserialize, Serialize };
+
+use crate::storage;
+
+const CAMERA_CONFIG_FILE: &str = "camera.ron";
+
+/// How the camera moves and how far the player may push it around. Missing fields in the file
+/// keep their defaults.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraConfig {
+    /// Seconds, roughly, the camera takes to catch up with where it should be
+    pub position_smoothing: f32,
+    /// The same for the point it looks at
+    pub target_smoothing: f32,
+    /// Keep the opponents and the cake in view while following the player
+    pub frame_all: bool,
+    /// World units of board kept in view around them
+    pub frame_margin: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    /// Radians above the board, from almost level to almost straight down
+    pub min_pitch: f32,
+    pub max_pitch: f32,
+    /// World units a pixel of panning moves the view, at the starting distance
+    pub pan_speed: f32,
+    /// Pixels a second the pan keys are worth
+    pub key_pan_speed: f32,
+    /// Radians a pixel of dragging turns the camera
+    pub orbit_speed: f32,
+    /// How much closer a line of scrolling brings the camera
+    pub zoom_step: f32,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        CameraConfig {
+            position_smoothing: 0.4,
+            target_smoothing: 0.25,
+            frame_all: true,
+            frame_margin: 1.5,
+            min_distance: 4.0,
+            max_distance: 30.0,
+            min_pitch: 0.15,
+            max_pitch: 1.45,
+            pan_speed: 0.02,
+            key_pan_speed: 600.0,
+            orbit_speed: 0.005,
+            zoom_step: 0.9,
+        }
+    }
+}
+
+pub fn load_camera_config(mut config: ResMut<CameraConfig>) {
+    match storage::load_ron::<CameraConfig>(CAMERA_CONFIG_FILE) {
+        Ok(Some(saved)) => *config = saved,
+        Ok(None) => info!("load_camera_config: no camera config, using the defaults"),
+        Err(e) => warn!("load_camera_config: {}, using the defaults", e),
+    }
+}
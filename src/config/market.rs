@@ -0,0 +1,116 @@
+use bevy::prelude::*;
+use serde::{ Deserialize, Serialize };
+
+use crate::storage;
+
+const MARKET_CONFIG_FILE: &str = "market.ron";
+
+/// Where the prices come from, see `api::feed::open_feed`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FeedBackend {
+    Binance,
+    /// Made up prices wandering off `start`, by up to `volatility` of the price at every poll
+    RandomWalk { seed: u64, start: f64, volatility: f64 },
+    /// Historical ticks from a CSV file, played back in a loop
+    Csv { path: String },
+}
+
+/// What a `MarketRule` pushes around, see `game::market::MarketEffects`
+#[derive(Copy, Clone, Debug, PartialEq, Hash, Serialize, Deserialize)]
+pub enum MarketTarget {
+    /// How often a new bonus turns up in real-time mode
+    BonusRate,
+    /// How high the water stands
+    WaterLevel,
+    PlayerSize,
+    /// How soon the player can take another step in real-time mode, and how fast it slides along a moved path
+    PlayerSpeed,
+}
+
+impl Eq for MarketTarget {}
+
+/// Moves `target` with the price of `symbol`, measured from where it stood when the level started.
+/// Every rule works on a level of 1 at that price, and several on the same target multiply.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MarketRule {
+    pub symbol: String,
+    pub target: MarketTarget,
+    /// What every percent the price moved adds to the level, negative to work against the price
+    pub gain: f32,
+    pub min: f32,
+    pub max: f32,
+    /// Seconds the level takes to ease into a new price
+    pub ease_seconds: f32,
+}
+
+/// Which market the game follows. Missing fields in the file keep their defaults.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MarketConfig {
+    pub backend: FeedBackend,
+    /// The pair the game follows
+    pub symbol: String,
+    /// Other pairs to keep an eye on, see `api::watch::MarketWatch`
+    pub symbols: Vec<String>,
+    /// Follow the trades over a WebSocket as they happen. Polling `backend` only fills in
+    /// while the stream is down.
+    pub streaming: bool,
+    /// Where to stream from, Binance's trade and ticker streams for every watched pair if not set
+    pub stream_url: Option<String>,
+    /// How many of the latest ticks are kept for every symbol
+    pub tick_window: usize,
+    /// How the prices play into the game
+    pub rules: Vec<MarketRule>,
+}
+
+impl Default for MarketConfig {
+    fn default() -> Self {
+        MarketConfig {
+            backend: FeedBackend::Binance,
+            symbol: String::from("HOTUSDT"),
+            symbols: vec![String::from("BTCUSDT"), String::from("ETHUSDT")],
+            streaming: false,
+            stream_url: None,
+            tick_window: 300,
+            rules: vec![
+                // a rising price bakes more cake, and a falling one floods the board
+                MarketRule::new("HOTUSDT", MarketTarget::BonusRate, 0.1, 0.5, 2.0, 3.0),
+                MarketRule::new("HOTUSDT", MarketTarget::WaterLevel, -0.1, 0.0, 2.0, 5.0),
+                MarketRule::new("HOTUSDT", MarketTarget::PlayerSize, 0.05, 0.5, 2.0, 1.0),
+                MarketRule::new("BTCUSDT", MarketTarget::PlayerSpeed, 0.05, 0.5, 2.0, 2.0),
+            ],
+        }
+    }
+}
+
+impl MarketRule {
+    pub fn new(symbol: &str, target: MarketTarget, gain: f32, min: f32, max: f32, ease_seconds: f32) -> Self {
+        MarketRule { symbol: String::from(symbol), target, gain, min, max, ease_seconds }
+    }
+
+    /// Where the level should go once the price moved by `change` percent
+    pub fn level(&self, change: f32) -> f32 {
+        (1.0 + self.gain * change).max(self.min).min(self.max)
+    }
+}
+
+impl MarketConfig {
+    /// Every pair to fetch, the one the game follows first, then the ones the rules need
+    pub fn watched(&self) -> Vec<String> {
+        let mut watched = vec![self.symbol.clone()];
+        for symbol in self.symbols.iter().chain(self.rules.iter().map(|rule| &rule.symbol)) {
+            if !watched.contains(symbol) {
+                watched.push(symbol.clone());
+            }
+        }
+        watched
+    }
+}
+
+pub fn load_market_config(mut config: ResMut<MarketConfig>) {
+    match storage::load_ron::<MarketConfig>(MARKET_CONFIG_FILE) {
+        Ok(Some(saved)) => *config = saved,
+        Ok(None) => info!("load_market_config: no market config, using the defaults"),
+        Err(e) => warn!("load_market_config: {}, using the defaults", e),
+    }
+}
@@ -0,0 +1,4 @@
+//! Tuning that lives in the save directory rather than in the code, so it can be changed without a rebuild
+
+pub mod camera;
+pub mod market;
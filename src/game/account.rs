@@ -0,0 +1,107 @@
+use bevy::prelude::*;
+use serde::{ Deserialize, Serialize };
+
+use crate::game::{ Game };
+use crate::game::player::CharacterType;
+use crate::game::replay::Replay;
+use crate::storage::{ self, StorageError };
+
+const ACCOUNT_FILE: &str = "account.ron";
+const MAX_HIGH_SCORES: usize = 10;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AccountStats {
+    pub games_played: u32,
+    pub total_score: i64,
+    pub best_score: i32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Account {
+    pub nickname: String,
+    pub avatar: CharacterType,
+    pub stats: AccountStats,
+    pub cake_eaten_total: u32,
+    /// Best scores first
+    pub high_scores: Vec<i32>,
+}
+
+impl Default for Account {
+    fn default() -> Self {
+        Account {
+            nickname: String::from("Player"),
+            avatar: CharacterType::Bunny,
+            stats: AccountStats::default(),
+            cake_eaten_total: 0,
+            high_scores: Vec::new(),
+        }
+    }
+}
+
+impl Account {
+    /// Fold a finished game into the stats
+    pub fn record_game(&mut self, score: i32, cake_eaten: u32) {
+        if self.stats.games_played == 0 || score > self.stats.best_score {
+            self.stats.best_score = score;
+        }
+        self.stats.games_played += 1;
+        self.stats.total_score += score as i64;
+        self.cake_eaten_total += cake_eaten;
+
+        self.high_scores.push(score);
+        self.high_scores.sort_by(|a, b| b.cmp(a));
+        self.high_scores.truncate(MAX_HIGH_SCORES);
+    }
+}
+
+#[derive(Debug)]
+pub enum SyncError {
+    Unavailable,
+    Rejected(String),
+}
+
+/// A remote place to keep the account, e.g. the profiles DNA
+// TODO: Implement on top of the conductor's app websocket once the profiles zome calls are wired up.
+pub trait AccountSync: Send + Sync {
+    fn push(&self, account: &Account) -> Result<(), SyncError>;
+    fn pull(&self, nickname: &str) -> Result<Option<Account>, SyncError>;
+}
+
+/// The local account, plus an optional backend to sync it with
+#[derive(Default)]
+pub struct Profiles {
+    pub account: Account,
+    pub sync: Option<Box<dyn AccountSync>>,
+}
+
+impl Profiles {
+    pub fn save(&self) -> Result<(), StorageError> {
+        storage::save_ron(ACCOUNT_FILE, &self.account)?;
+        if let Some(sync) = &self.sync {
+            if let Err(e) = sync.push(&self.account) {
+                warn!("Profiles::save(): sync failed: {:?}", e);
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn load_account(mut profiles: ResMut<Profiles>) {
+    match storage::load_ron::<Account>(ACCOUNT_FILE) {
+        Ok(Some(account)) => profiles.account = account,
+        Ok(None) => info!("load_account: no saved account, starting fresh"),
+        Err(e) => warn!("load_account: {}", e),
+    }
+}
+
+// add the finished game to the player's stats
+pub fn record_game_over(game: Res<Game>, replay: Res<Replay>, mut profiles: ResMut<Profiles>) {
+    // watching a replay isn't playing
+    if replay.playing {
+        return;
+    }
+    profiles.account.record_game(game.score, game.cake_eaten);
+    if let Err(e) = profiles.save() {
+        warn!("record_game_over: {}", e);
+    }
+}
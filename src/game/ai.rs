@@ -0,0 +1,302 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_mod_picking::{ PickableBundle, BoundVol };
+use rand::{ Rng, RngCore };
+use serde::{ Deserialize, Serialize };
+
+use crate::game::{ Game };
+use crate::game::board::{ BoardPos, find_path };
+use crate::game::level::{ LevelSetup, SetupStage };
+use crate::game::player::{ CharacterType };
+use crate::game::replay::{ GameRng, SimClock };
+use crate::game::turns::{ Actor, Ruleset, TurnState };
+use crate::assets::{ AssetIndex };
+use crate::input::{ GameCommand, MoveTarget };
+
+#[derive(Copy, Clone, Debug, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+impl Eq for Difficulty {}
+
+impl Difficulty {
+    /// Seconds between two steps of an NPC
+    pub fn step_interval(&self) -> f32 {
+        match self {
+            Difficulty::Easy => 1.2,
+            Difficulty::Normal => 0.8,
+            Difficulty::Hard => 0.45,
+        }
+    }
+
+    /// Chance that an NPC ignores its behaviour for a step and wanders off instead
+    pub fn blunder_chance(&self) -> f64 {
+        match self {
+            Difficulty::Easy => 0.35,
+            Difficulty::Normal => 0.15,
+            Difficulty::Hard => 0.0,
+        }
+    }
+}
+
+/// Everything an NPC gets to look at when deciding on its next step
+pub struct WorldView<'a> {
+    pub game: &'a Game,
+    pub me: BoardPos,
+    pub size_i: usize,
+    pub size_j: usize,
+}
+
+impl<'a> WorldView<'a> {
+    pub fn player(&self) -> BoardPos {
+        BoardPos::new(self.game.player.i, self.game.player.j)
+    }
+
+    /// Where the bonus is, if there is one on the board
+    pub fn bonus(&self) -> Option<BoardPos> {
+        self.game.bonus.entity.map(|_| BoardPos::new(self.game.bonus.i, self.game.bonus.j))
+    }
+
+    pub fn path_to(&self, from: BoardPos, to: BoardPos) -> Option<Vec<BoardPos>> {
+        find_path(from, to, self.size_i, self.size_j, |_| true)
+    }
+
+    /// The first step on the shortest path from `me` towards `target`
+    pub fn step_towards(&self, target: BoardPos) -> Option<BoardPos> {
+        self.path_to(self.me, target).and_then(|path| path.get(1).copied())
+    }
+}
+
+/// A pluggable NPC brain. The step it picks is sent as a `GameCommand::Move`, just like the player's.
+pub trait Behaviour: Send + Sync {
+    fn name(&self) -> &'static str;
+    /// The next cell to step on. Must be a neighbour of `view.me`, or `None` to stay put.
+    fn next_step(&self, view: &WorldView, rng: &mut dyn RngCore) -> Option<BoardPos>;
+}
+
+/// Walks towards the bonus along the shortest path
+pub struct GreedyNearest;
+
+impl Behaviour for GreedyNearest {
+    fn name(&self) -> &'static str { "Greedy" }
+
+    fn next_step(&self, view: &WorldView, rng: &mut dyn RngCore) -> Option<BoardPos> {
+        match view.bonus() {
+            Some(bonus) => view.step_towards(bonus),
+            None => RandomWander.next_step(view, rng),
+        }
+    }
+}
+
+/// Picks a random neighbour every step
+pub struct RandomWander;
+
+impl Behaviour for RandomWander {
+    fn name(&self) -> &'static str { "Wanderer" }
+
+    fn next_step(&self, view: &WorldView, rng: &mut dyn RngCore) -> Option<BoardPos> {
+        let neighbors = view.me.neighbors(view.size_i, view.size_j);
+        if neighbors.is_empty() {
+            return None;
+        }
+        Some(neighbors[rng.gen_range(0..neighbors.len())])
+    }
+}
+
+/// Cuts the player off on their way to the bonus, or goes for the bonus itself when it's closer
+pub struct Interceptor;
+
+impl Behaviour for Interceptor {
+    fn name(&self) -> &'static str { "Interceptor" }
+
+    fn next_step(&self, view: &WorldView, rng: &mut dyn RngCore) -> Option<BoardPos> {
+        let bonus = match view.bonus() {
+            Some(bonus) => bonus,
+            None => return view.step_towards(view.player()),
+        };
+        if view.me.distance(&bonus) <= view.player().distance(&bonus) {
+            return GreedyNearest.next_step(view, rng);
+        }
+        // Aim for the first cell on the player's path that we can reach no later than the player
+        let target = view
+            .path_to(view.player(), bonus)
+            .and_then(|path| {
+                path.into_iter()
+                    .enumerate()
+                    .find(|(player_steps, cell)| view.me.distance(cell) <= *player_steps)
+                    .map(|(_, cell)| cell)
+            })
+            .unwrap_or(bonus);
+        view.step_towards(target)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Hash, Serialize, Deserialize)]
+pub enum BehaviourType {
+    Greedy,
+    Wander,
+    Interceptor,
+}
+impl Eq for BehaviourType {}
+
+impl BehaviourType {
+    pub fn build(&self) -> Box<dyn Behaviour> {
+        match self {
+            BehaviourType::Greedy => Box::new(GreedyNearest),
+            BehaviourType::Wander => Box::new(RandomWander),
+            BehaviourType::Interceptor => Box::new(Interceptor),
+        }
+    }
+}
+
+/// The opponents spawned with every level
+pub struct NpcSettings {
+    pub opponents: Vec<(BehaviourType, Difficulty)>,
+}
+
+impl Default for NpcSettings {
+    fn default() -> Self {
+        NpcSettings {
+            opponents: vec![
+                (BehaviourType::Greedy, Difficulty::Normal),
+                (BehaviourType::Interceptor, Difficulty::Normal),
+            ],
+        }
+    }
+}
+
+pub struct Npc {
+    pub pos: BoardPos,
+    pub behaviour: Box<dyn Behaviour>,
+    pub difficulty: Difficulty,
+    pub step_timer: Timer,
+    pub score: i32,
+    pub cake_eaten: u32,
+}
+
+impl Npc {
+    pub fn new(pos: BoardPos, behaviour: BehaviourType, difficulty: Difficulty) -> Self {
+        Npc {
+            pos,
+            behaviour: behaviour.build(),
+            difficulty,
+            step_timer: Timer::from_seconds(difficulty.step_interval(), true),
+            score: 0,
+            cake_eaten: 0,
+        }
+    }
+
+    /// Run the step timer on by `delta`, and pick a step when it's due. Returns the cell to step on,
+    /// which `input::move_actors` moves the NPC to once it's paid for.
+    pub fn update(
+        &mut self,
+        actor: Actor,
+        delta: Duration,
+        game: &Game,
+        ruleset: &Ruleset,
+        turns: &mut TurnState,
+        rng: &mut dyn RngCore,
+    ) -> Option<BoardPos> {
+        if !self.step_timer.tick(delta).just_finished() {
+            return None;
+        }
+        if ruleset.is_turn_based() && turns.current_actor() != Some(actor) {
+            return None;
+        }
+
+        let (size_i, size_j) = game.board_size();
+        let view = WorldView { game, me: self.pos, size_i, size_j };
+        let next = if rng.gen_bool(self.difficulty.blunder_chance()) {
+            RandomWander.next_step(&view, rng)
+        } else {
+            self.behaviour.next_step(&view, rng)
+        };
+
+        // stuck: hand over to the next actor
+        if next.is_none() && ruleset.is_turn_based() {
+            turns.end_turn();
+        }
+        next
+    }
+}
+
+/// A random cell for a new NPC, off the player and the NPCs already `taken` their place,
+/// as long as the board has room left
+pub fn start_cell(game: &Game, taken: &[BoardPos], rng: &mut impl Rng) -> BoardPos {
+    let (size_i, size_j) = game.board_size();
+    let player = BoardPos::new(game.player.i, game.player.j);
+    loop {
+        let pos = BoardPos::random(size_i, size_j, rng);
+        if (pos != player && !taken.contains(&pos)) || taken.len() + 1 >= size_i * size_j {
+            return pos;
+        }
+    }
+}
+
+/// The opponents of `settings`, each on its start cell
+pub fn place_npcs(game: &Game, settings: &NpcSettings, rng: &mut impl Rng) -> Vec<Npc> {
+    let mut taken = Vec::new();
+    let mut npcs = Vec::new();
+    for (behaviour, difficulty) in settings.opponents.iter() {
+        let pos = start_cell(game, &taken, rng);
+        taken.push(pos);
+        npcs.push(Npc::new(pos, *behaviour, *difficulty));
+    }
+    npcs
+}
+
+pub fn spawn_npcs(
+    mut commands: Commands,
+    game: Res<Game>,
+    settings: Res<NpcSettings>,
+    mut rng: ResMut<GameRng>,
+    asset_index: Res<AssetIndex>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut setup: ResMut<LevelSetup>,
+) {
+    // runs after the player, so a failed player spawn has already stopped the stage
+    if !setup.is_running(SetupStage::Entities) {
+        return;
+    }
+    let npc_mesh = match asset_index.mesh_by_type.get(&CharacterType::Monkey) {
+        Some(mesh) => mesh.clone(),
+        None => return setup.fail(String::from("spawn_npcs: NPC mesh asset missing.")),
+    };
+    for npc in place_npcs(&game, &settings, &mut rng.0) {
+        let mut transform = Transform::from_translation(game.cell_translation(npc.pos));
+        transform.apply_non_uniform_scale(Vec3::new(0.2, 0.2, 0.2));
+
+        commands.spawn_bundle(PbrBundle {
+            mesh: npc_mesh.clone(),
+            transform,
+            material: materials.add(Color::rgb(0.6, 0.3, 0.3).into()),
+            ..Default::default()
+        })
+        .insert_bundle(PickableBundle::default())
+        .insert(BoundVol::default())
+        .insert(npc);
+    }
+    setup.advance();
+}
+
+/// Let every NPC pick a step per tick of its own timer, and send it as a move like the player's.
+/// In turn-based mode an NPC only moves on its own turn, paying for every step.
+pub fn npc_step(
+    clock: Res<SimClock>,
+    game: Res<Game>,
+    mut rng: ResMut<GameRng>,
+    ruleset: Res<Ruleset>,
+    mut turns: ResMut<TurnState>,
+    mut npcs: Query<(Entity, &mut Npc)>,
+    mut game_commands: EventWriter<GameCommand>,
+) {
+    for (entity, mut npc) in npcs.iter_mut() {
+        let actor = Actor::Npc(entity);
+        if let Some(next) = npc.update(actor, clock.delta, &game, &ruleset, &mut turns, &mut rng.0) {
+            game_commands.send(GameCommand::Move { actor, to: MoveTarget::Cell(next) });
+        }
+    }
+}
@@ -1,10 +1,14 @@
 use crate::{ BOARD_SIZE_I, BOARD_SIZE_J };
 use bevy::prelude::*;
+use bevy::utils::HashMap;
 use rand::Rng;
+use serde::{ Deserialize, Serialize };
+use std::collections::VecDeque;
 
-use crate::game::components::TileType;
+use crate::game::components::{ TileType, Terrain };
 use super::{Cell, Game, BoardParams};
 use crate::assets::AssetIndex;
+use crate::hextiles::hex::HexCoord;
 
 pub fn setup_board(board_params: ResMut<BoardParams>, width: u32, height: u32) {
 
@@ -27,9 +31,111 @@ pub fn spawn_board(mut commands: Commands, mut game: ResMut<Game>, asset_index:
                         .with_children(|cell| {
                             cell.spawn_scene(cell_scene.clone());
                         });
-                    Cell { height }
+                    Cell { height, terrain: Terrain::Grass, building: None, entity: None, stock: Terrain::Grass.stock_capacity() }
                 })
                 .collect()
         })
         .collect();
 }
+
+/// A cell on the board, indexed the same way as `Game::board[j][i]`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct BoardPos {
+    pub i: usize,
+    pub j: usize,
+}
+
+impl BoardPos {
+    pub fn new(i: usize, j: usize) -> Self {
+        BoardPos { i, j }
+    }
+
+    /// The hex coordinate of the cell. `hextiles::sample_level` lays the rows out along `i`, odd rows shifted.
+    pub fn hex(&self) -> HexCoord {
+        HexCoord::from_odd_r(self.j as isize, self.i as isize)
+    }
+
+    /// The number of single-cell steps between two cells, ignoring terrain
+    pub fn distance(&self, other: &BoardPos) -> usize {
+        self.hex().distance(&other.hex())
+    }
+
+    /// The neighbouring cells that lie on a board of `size_i` x `size_j` cells
+    pub fn neighbors(&self, size_i: usize, size_j: usize) -> Vec<BoardPos> {
+        self.hex()
+            .neighbors()
+            .map(|n| n.to_odd_r())
+            .filter(|(col, row)| *row >= 0 && *col >= 0 && (*row as usize) < size_i && (*col as usize) < size_j)
+            .map(|(col, row)| BoardPos::new(row as usize, col as usize))
+            .collect()
+    }
+
+    /// Any cell of a board of `size_i` x `size_j` cells
+    pub fn random(size_i: usize, size_j: usize, rng: &mut impl Rng) -> BoardPos {
+        BoardPos::new(rng.gen_range(0..size_i.max(1)), rng.gen_range(0..size_j.max(1)))
+    }
+}
+
+/// Breadth-first search over the board graph, from `from` to `to` (both included).
+/// Cells for which `passable` returns false are never entered, except for the target itself.
+pub fn find_path(
+    from: BoardPos,
+    to: BoardPos,
+    size_i: usize,
+    size_j: usize,
+    passable: impl Fn(BoardPos) -> bool,
+) -> Option<Vec<BoardPos>> {
+    let mut came_from: HashMap<BoardPos, BoardPos> = HashMap::default();
+    let mut frontier = VecDeque::new();
+    frontier.push_back(from);
+    came_from.insert(from, from);
+
+    while let Some(current) = frontier.pop_front() {
+        if current == to {
+            let mut path = vec![current];
+            let mut step = current;
+            while step != from {
+                step = came_from[&step];
+                path.push(step);
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for next in current.neighbors(size_i, size_j) {
+            if came_from.contains_key(&next) || (next != to && !passable(next)) {
+                continue;
+            }
+            came_from.insert(next, current);
+            frontier.push_back(next);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn neighbors_stay_on_board() {
+        assert_eq!(2, BoardPos::new(0, 0).neighbors(4, 4).len());
+        assert_eq!(6, BoardPos::new(1, 1).neighbors(4, 4).len());
+        for n in BoardPos::new(3, 3).neighbors(4, 4) {
+            assert!(n.i < 4 && n.j < 4);
+            assert_eq!(1, BoardPos::new(3, 3).distance(&n));
+        }
+    }
+
+    #[test]
+    fn path_is_shortest_and_avoids_blocked_cells() {
+        let (from, to) = (BoardPos::new(0, 0), BoardPos::new(5, 5));
+        let path = find_path(from, to, 8, 8, |_| true).unwrap();
+        assert_eq!(from.distance(&to) + 1, path.len());
+        assert_eq!(Some(&to), path.last());
+
+        let wall = BoardPos::new(2, 2);
+        let path = find_path(from, to, 8, 8, |p| p != wall).unwrap();
+        assert!(!path.contains(&wall));
+        assert_eq!(None, find_path(from, to, 8, 8, |p| p.i != 3));
+    }
+}
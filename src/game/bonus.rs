@@ -1,49 +1,120 @@
+use std::time::Duration;
+
 use bevy::prelude::*;
+use bevy::ecs::schedule::ShouldRun;
 
 use crate::game::{ Game, GameState };
-use crate::{ BOARD_SIZE_J, BOARD_SIZE_I };
+use crate::game::board::BoardPos;
+use crate::game::fog::FogOfWar;
+use crate::game::replay::{ GameRng, SimClock };
+use crate::game::turns::Ruleset;
 use crate::PickaBundle;
 
 use bevy_mod_picking::{BoundVol, PickableBundle};
 
 use rand::Rng;
 
+/// Seconds between two bonuses in real-time mode
+const BONUS_INTERVAL: f32 = 5.0;
+/// What eating a bonus is worth
+pub const BONUS_POINTS: i32 = 2;
+/// What letting a bonus go to waste costs the player
+pub const MISSED_BONUS_PENALTY: i32 = 3;
+/// The game is over once the score drops this low
+pub const GAME_OVER_SCORE: i32 = -180;
+
+/// Only runs down while playing, so pausing doesn't cost the player their cake
+pub struct BonusTimer(pub Timer);
+
+impl Default for BonusTimer {
+    fn default() -> Self {
+        BonusTimer(Timer::from_seconds(BONUS_INTERVAL, true))
+    }
+}
+
+impl BonusTimer {
+    /// Bonuses come `rate` times as often as usual, see `market::market_bonus_rate`
+    pub fn set_rate(&mut self, rate: f32) {
+        // no rate at all would mean a timer that never runs out
+        self.0.set_duration(Duration::from_secs_f32(BONUS_INTERVAL / rate.max(0.1)));
+    }
+}
+
+pub fn bonus_timer_finished(clock: Res<SimClock>, mut timer: ResMut<BonusTimer>) -> ShouldRun {
+    // the clock stands still unless playing
+    if clock.running && timer.0.tick(clock.delta).just_finished() {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+/// Take the bonus off the board if it's on `pos`, returning its entity
+pub fn take_bonus_at(game: &mut Game, pos: BoardPos) -> Option<Entity> {
+    if pos != BoardPos::new(game.bonus.i, game.bonus.j) {
+        return None;
+    }
+    game.bonus.entity.take()
+}
+
+/// Take away a bonus nobody got to in time, charging the player for it. Returns its entity.
+pub fn miss_bonus(game: &mut Game) -> Option<Entity> {
+    let entity = game.bonus.entity.take()?;
+    game.score -= MISSED_BONUS_PENALTY;
+    Some(entity)
+}
+
+pub fn is_game_over(game: &Game) -> bool {
+    game.score <= GAME_OVER_SCORE
+}
+
+/// Pick the cell for the next bonus, anywhere but under the player
+pub fn place_bonus(game: &mut Game, rng: &mut impl Rng) {
+    let (size_i, size_j) = game.board_size();
+    let player = BoardPos::new(game.player.i, game.player.j);
+    loop {
+        let pos = BoardPos::random(size_i, size_j, rng);
+        if pos != player || size_i * size_j < 2 {
+            game.bonus.i = pos.i;
+            game.bonus.j = pos.j;
+            break;
+        }
+    }
+}
+
 // despawn the bonus if there is one, then spawn a new one at a random location
-// Run criteria: fixed time step
+// Run criteria: bonus timer
 pub fn spawn_bonus(
     mut state: ResMut<State<GameState>>,
     mut commands: Commands,
     mut game: ResMut<Game>,
-    mut asset_server: ResMut<AssetServer>
+    mut rng: ResMut<GameRng>,
+    ruleset: Res<Ruleset>,
 ) {
     if *state.current() != GameState::Playing {
         return;
     }
-    if let Some(entity) = game.bonus.entity {
-        game.score -= 3;
+    // In turn-based mode, bonuses expire by turns instead (see `turns::expire_bonus`)
+    if ruleset.is_turn_based() {
+        return;
+    }
+    replace_bonus(&mut state, &mut commands, &mut game, &mut rng);
+}
+
+/// Take away the current bonus (costing the player some score), then put a new one on the board
+pub fn replace_bonus(state: &mut State<GameState>, commands: &mut Commands, game: &mut Game, rng: &mut GameRng) {
+    if let Some(entity) = miss_bonus(game) {
         commands.entity(entity).despawn_recursive();
-        game.bonus.entity = None;
-        if game.score <= -180 {
+        if is_game_over(game) {
             state.set(GameState::GameOver).unwrap();
             return;
         }
     }
 
-    // ensure bonus doesn't spawn on the player
-    loop {
-        game.bonus.i = rand::thread_rng().gen_range(0..BOARD_SIZE_I);
-        game.bonus.j = rand::thread_rng().gen_range(0..BOARD_SIZE_J);
-        if game.bonus.i != game.player.i || game.bonus.j != game.player.j {
-            break;
-        }
-    }
+    place_bonus(game, &mut rng.0);
     let pibun: PickaBundle = PickaBundle {
         transform: Transform {
-            translation: Vec3::new(
-                game.bonus.i as f32,
-                game.board[game.player.j][game.player.i].height + 0.2,
-                game.bonus.j as f32,
-            ),
+            translation: game.cell_translation(BoardPos::new(game.bonus.i, game.bonus.j)) + Vec3::Y * 0.2,
             ..Default::default()
         },
         global_transform: GlobalTransform::identity(),
@@ -64,9 +135,14 @@ pub fn spawn_bonus(
 }
 
 // let the cake turn on itself
-pub fn rotate_bonus(game: Res<Game>, time: Res<Time>, mut transforms: Query<&mut Transform>) {
+pub fn rotate_bonus(game: Res<Game>, fog: Res<FogOfWar>, time: Res<Time>, mut transforms: Query<&mut Transform>) {
     if let Some(entity) = game.bonus.entity {
         if let Ok(mut cake_transform) = transforms.get_mut(entity) {
+            // the cake has to be found first
+            if !fog.is_visible(BoardPos::new(game.bonus.i, game.bonus.j)) {
+                cake_transform.scale = Vec3::ZERO;
+                return;
+            }
             cake_transform.rotate(Quat::from_rotation_y(time.delta_seconds()));
             let scale = 1.0 + (/* game.score as f32 / 1.0 * */ time.seconds_since_startup().sin() as f32).abs();
             // info!("rotate_bonus:: scale:: {:?}", scale);
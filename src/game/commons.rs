@@ -0,0 +1,164 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use crate::game::{ Game, BoardColors };
+use crate::game::board::BoardPos;
+use crate::game::fog::{ FogOfWar, fog_tint };
+use crate::game::replay::SimClock;
+use crate::game::turns::Actor;
+
+/// Seconds between two regrowth steps
+const REGROWTH_STEP: f32 = 1.0;
+
+pub struct Commons {
+    pub timer: Timer,
+    /// Intrinsic growth rate of the logistic regrowth, per step
+    pub regrowth_rate: f32,
+    /// Set whenever a stock or the fog changed and the tiles need recolouring
+    pub dirty: bool,
+}
+
+impl Default for Commons {
+    fn default() -> Self {
+        Commons {
+            timer: Timer::from_seconds(REGROWTH_STEP, true),
+            regrowth_rate: 0.25,
+            dirty: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HarvestRecord {
+    pub harvested: f32,
+    /// The part of the harvest that pushed a stock below its sustainable level
+    pub overharvested: f32,
+}
+
+/// Who took how much out of the commons
+#[derive(Default)]
+pub struct HarvestLedger(pub HashMap<Actor, HarvestRecord>);
+
+impl HarvestLedger {
+    pub fn record(&self, actor: Actor) -> HarvestRecord {
+        self.0.get(&actor).cloned().unwrap_or_default()
+    }
+}
+
+/// One logistic regrowth step. The stock regrows proportionally to the average of its own and its
+/// neighbours' stock, so a fully depleted patch never comes back on its own.
+pub fn regrow(stock: f32, capacity: f32, neighbor_mean: f32, rate: f32) -> f32 {
+    let seed = (stock + neighbor_mean) / 2.0;
+    let growth = rate * seed * (1.0 - stock / capacity);
+    (stock + growth).max(0.0).min(capacity)
+}
+
+/// Take up to `wanted` out of `stock`, in whole units since that's what goes into the inventory.
+/// Returns `(taken, overharvested)`, where the latter is the part of the take below half the capacity,
+/// i.e. past the maximum sustainable yield of the logistic curve.
+pub fn harvest(stock: &mut f32, capacity: f32, wanted: f32) -> (f32, f32) {
+    // what regrowth left over a whole unit stays on the cell
+    let taken = wanted.min(*stock).floor();
+    *stock -= taken;
+    let overharvested = (capacity / 2.0 - *stock).max(0.0).min(taken);
+    (taken, overharvested)
+}
+
+/// Let every cell regrow from its own and its neighbours' stock
+pub fn regrow_commons(clock: Res<SimClock>, mut commons: ResMut<Commons>, mut game: ResMut<Game>) {
+    if !commons.timer.tick(clock.delta).just_finished() {
+        return;
+    }
+    regrow_board(&mut game, commons.regrowth_rate);
+    commons.dirty = true;
+}
+
+/// One regrowth step for every cell of the board at once
+pub fn regrow_board(game: &mut Game, rate: f32) {
+    let (size_i, size_j) = game.board_size();
+    let stocks: Vec<Vec<f32>> = game.board.iter().map(|row| row.iter().map(|cell| cell.stock).collect()).collect();
+
+    for j in 0..size_j {
+        for i in 0..size_i {
+            let neighbors = BoardPos::new(i, j).neighbors(size_i, size_j);
+            let neighbor_mean = if neighbors.is_empty() {
+                0.0
+            } else {
+                neighbors.iter().map(|n| stocks[n.j][n.i]).sum::<f32>() / neighbors.len() as f32
+            };
+            let cell = &mut game.board[j][i];
+            cell.stock = regrow(cell.stock, cell.terrain.stock_capacity(), neighbor_mean, rate);
+        }
+    }
+}
+
+/// Fade each tile from its terrain colour towards `BoardColors::depleted` as its stock runs out
+pub fn stock_tint(
+    mut commons: ResMut<Commons>,
+    game: Res<Game>,
+    board_colors: Res<BoardColors>,
+    fog: Res<FogOfWar>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    tiles: Query<&Handle<StandardMaterial>>,
+) {
+    if !commons.dirty {
+        return;
+    }
+    for (j, row) in game.board.iter().enumerate() {
+        for (i, cell) in row.iter().enumerate() {
+            let handle = match cell.entity.and_then(|entity| tiles.get(entity).ok()) {
+                Some(handle) => handle,
+                None => continue,
+            };
+            if let Some(material) = materials.get_mut(handle) {
+                let full = board_colors.colors[cell.terrain as usize];
+                let fill = cell.stock / cell.terrain.stock_capacity();
+                let stocked = lerp_color(board_colors.depleted, full, fill);
+                material.base_color = fog_tint(stocked, fog.visibility(BoardPos::new(i, j)));
+            }
+        }
+    }
+    commons.dirty = false;
+}
+
+pub fn lerp_color(from: Color, to: Color, t: f32) -> Color {
+    let (from, to) = (Vec4::from(from), Vec4::from(to));
+    from.lerp(to, t.max(0.0).min(1.0)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regrowth_is_logistic() {
+        assert_eq!(0.0, regrow(0.0, 10.0, 0.0, 0.5));
+        assert!(regrow(0.0, 10.0, 5.0, 0.5) > 0.0);
+        let mut stock = 1.0;
+        for _ in 0..200 {
+            let next = regrow(stock, 10.0, stock, 0.5);
+            assert!(next >= stock && next <= 10.0);
+            stock = next;
+        }
+        assert!(stock > 9.9);
+    }
+
+    #[test]
+    fn overharvest_counts_the_take_below_half_capacity() {
+        let mut stock = 10.0;
+        assert_eq!((3.0, 0.0), harvest(&mut stock, 10.0, 3.0));
+        assert_eq!((3.0, 1.0), harvest(&mut stock, 10.0, 3.0));
+        assert_eq!((3.0, 3.0), harvest(&mut stock, 10.0, 3.0));
+        assert_eq!((1.0, 1.0), harvest(&mut stock, 10.0, 3.0));
+        assert_eq!(0.0, stock);
+    }
+
+    #[test]
+    fn fractional_stock_stays_on_the_cell() {
+        let mut stock = 2.5;
+        assert_eq!((2.0, 2.0), harvest(&mut stock, 10.0, 3.0));
+        assert_eq!(0.5, stock);
+        assert_eq!((0.0, 0.0), harvest(&mut stock, 10.0, 3.0));
+        assert_eq!(0.5, stock);
+    }
+}
@@ -8,3 +8,41 @@ pub enum TileType {
 }
 impl Eq for TileType {}
 
+
+/// What a board cell is made of. Decides movement costs, what grows there and what can be seen past it.
+#[derive(Copy, Clone, Debug, PartialEq, Hash)]
+pub enum Terrain {
+    Water,
+    Grass,
+    Hills,
+}
+impl Eq for Terrain {}
+
+impl Terrain {
+    /// Action points it takes to step onto a cell of this terrain
+    pub fn movement_cost(&self) -> u32 {
+        match self {
+            Terrain::Water => 2,
+            Terrain::Grass => 1,
+            Terrain::Hills => 3,
+        }
+    }
+
+    /// How much renewable stock a cell of this terrain can hold
+    pub fn stock_capacity(&self) -> f32 {
+        match self {
+            Terrain::Water => 6.0,
+            Terrain::Grass => 10.0,
+            Terrain::Hills => 4.0,
+        }
+    }
+
+    /// Whether a cell of this terrain can hide what's behind it
+    pub fn blocks_sight(&self) -> bool {
+        match self {
+            Terrain::Water => false,
+            Terrain::Grass => false,
+            Terrain::Hills => true,
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use serde::{ Deserialize, Serialize };
+
+use crate::game::{ Game };
+use crate::game::board::BoardPos;
+use crate::game::components::Terrain;
+use crate::game::replay::SimClock;
+use crate::game::turns::{ Actor, Ruleset, TurnState };
+use crate::game::commons::{ Commons, HarvestLedger, HarvestRecord, harvest };
+use crate::assets::AssetIndex;
+use crate::input::GameCommand;
+
+#[derive(Copy, Clone, Debug, PartialEq, Hash)]
+pub enum ResourceKind {
+    Apple,
+    Fish,
+    Stone,
+    Pizza,
+}
+impl Eq for ResourceKind {}
+
+pub const RESOURCE_KINDS: [ResourceKind; 4] = [ResourceKind::Apple, ResourceKind::Fish, ResourceKind::Stone, ResourceKind::Pizza];
+
+/// What a single harvest of a cell of this terrain brings in
+pub fn terrain_yield(terrain: Terrain) -> (ResourceKind, u32) {
+    match terrain {
+        Terrain::Water => (ResourceKind::Fish, 1),
+        Terrain::Grass => (ResourceKind::Apple, 2),
+        Terrain::Hills => (ResourceKind::Stone, 1),
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Inventory(pub HashMap<ResourceKind, u32>);
+
+impl Inventory {
+    pub fn count(&self, kind: ResourceKind) -> u32 {
+        *self.0.get(&kind).unwrap_or(&0)
+    }
+
+    pub fn add(&mut self, kind: ResourceKind, amount: u32) {
+        *self.0.entry(kind).or_insert(0) += amount;
+    }
+
+    pub fn can_afford(&self, cost: &[(ResourceKind, u32)]) -> bool {
+        cost.iter().all(|(kind, amount)| self.count(*kind) >= *amount)
+    }
+
+    /// Take `cost` out of the inventory, all or nothing
+    pub fn pay(&mut self, cost: &[(ResourceKind, u32)]) -> bool {
+        if !self.can_afford(cost) {
+            return false;
+        }
+        for (kind, amount) in cost {
+            *self.0.entry(*kind).or_insert(0) -= amount;
+        }
+        true
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Hash, Serialize, Deserialize)]
+pub enum BuildingType {
+    House,
+    PizzaShop,
+}
+impl Eq for BuildingType {}
+
+impl BuildingType {
+    pub fn cost(&self) -> &'static [(ResourceKind, u32)] {
+        match self {
+            BuildingType::House => &[(ResourceKind::Stone, 3), (ResourceKind::Apple, 2)],
+            BuildingType::PizzaShop => &[(ResourceKind::Stone, 4), (ResourceKind::Apple, 3), (ResourceKind::Fish, 2)],
+        }
+    }
+
+    pub fn allowed_on(&self, terrain: Terrain) -> bool {
+        match self {
+            BuildingType::House => terrain != Terrain::Water,
+            BuildingType::PizzaShop => terrain == Terrain::Grass,
+        }
+    }
+
+    /// What the building makes, and how many seconds it takes
+    pub fn production(&self) -> (ResourceKind, u32, f32) {
+        match self {
+            BuildingType::House => (ResourceKind::Apple, 1, 10.0),
+            BuildingType::PizzaShop => (ResourceKind::Pizza, 1, 6.0),
+        }
+    }
+}
+
+pub struct Building {
+    pub kind: BuildingType,
+    pub pos: BoardPos,
+    pub production_timer: Timer,
+}
+
+impl Building {
+    pub fn new(kind: BuildingType, pos: BoardPos) -> Self {
+        let (_, _, seconds) = kind.production();
+        Building { kind, pos, production_timer: Timer::from_seconds(seconds, true) }
+    }
+
+    /// Run the production timer on by `delta`, delivering into `inventory` whenever it's done
+    pub fn produce(&mut self, delta: Duration, inventory: &mut Inventory) {
+        if self.production_timer.tick(delta).just_finished() {
+            let (kind, amount, _) = self.kind.production();
+            inventory.add(kind, amount);
+        }
+    }
+}
+
+/// A harvested resource briefly popping up over its cell
+pub struct HarvestPop(Timer);
+
+/// Harvest the cell the player stands on, as far as its commons stock allows,
+/// or build a house or a pizza shop there. Each of those takes an action point in turn-based mode.
+pub fn economy_commands(
+    mut commands: Commands,
+    mut game_commands: EventReader<GameCommand>,
+    mut game: ResMut<Game>,
+    ruleset: Res<Ruleset>,
+    mut turns: ResMut<TurnState>,
+    mut commons: ResMut<Commons>,
+    mut ledger: ResMut<HarvestLedger>,
+    asset_index: Res<AssetIndex>,
+) {
+    for command in game_commands.iter() {
+        let pos = BoardPos::new(game.player.i, game.player.j);
+        match command {
+            GameCommand::Harvest => {
+                if turns.try_spend(&ruleset, Actor::Player, 1) {
+                    harvest_cell(&mut commands, &mut game, &mut commons, &mut ledger, &asset_index, pos);
+                }
+            }
+            GameCommand::PlaceBuilding { kind } => {
+                place_building(&mut commands, &mut game, &ruleset, &mut turns, &asset_index, *kind, pos);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Take the player's yield off the cell at `pos` into their inventory, booking it in the ledger.
+/// Returns what was taken.
+pub fn harvest_at(game: &mut Game, ledger: &mut HarvestLedger, pos: BoardPos) -> (ResourceKind, u32) {
+    let cell = &mut game.board[pos.j][pos.i];
+    let (kind, amount) = terrain_yield(cell.terrain);
+    let capacity = cell.terrain.stock_capacity();
+    let (taken, overharvested) = harvest(&mut cell.stock, capacity, amount as f32);
+    game.player.inventory.add(kind, taken as u32);
+
+    let record = ledger.0.entry(Actor::Player).or_insert_with(HarvestRecord::default);
+    record.harvested += taken;
+    record.overharvested += overharvested;
+    (kind, taken as u32)
+}
+
+fn harvest_cell(
+    commands: &mut Commands,
+    game: &mut Game,
+    commons: &mut Commons,
+    ledger: &mut HarvestLedger,
+    asset_index: &AssetIndex,
+    pos: BoardPos,
+) {
+    let (kind, _) = harvest_at(game, ledger, pos);
+    commons.dirty = true;
+
+    if let Some(scene) = asset_index.scene_by_resource.get(&kind) {
+        let mut transform = Transform::from_translation(game.cell_translation(pos) + Vec3::Y * 0.5);
+        transform.apply_non_uniform_scale(Vec3::splat(0.3));
+        commands
+            .spawn_bundle((transform, GlobalTransform::identity()))
+            .with_children(|parent| {
+                parent.spawn_scene(scene.clone());
+            })
+            .insert(HarvestPop(Timer::from_seconds(0.6, false)));
+    }
+}
+
+/// Pay for a building on `pos`, if the terrain allows it, the cell is free and the player can afford it
+/// along with the action point it takes in turn-based mode. Returns whether it was paid for.
+pub fn pay_for_building(game: &mut Game, ruleset: &Ruleset, turns: &mut TurnState, kind: BuildingType, pos: BoardPos) -> bool {
+    let cell = &game.board[pos.j][pos.i];
+    if cell.building.is_some() || !kind.allowed_on(cell.terrain) {
+        info!("pay_for_building: can't put a {:?} on {:?}", kind, pos);
+        return false;
+    }
+    if !game.player.inventory.can_afford(kind.cost()) || !turns.try_spend(ruleset, Actor::Player, 1) {
+        info!("pay_for_building: can't afford a {:?}", kind);
+        return false;
+    }
+    game.player.inventory.pay(kind.cost())
+}
+
+/// Put a building on `pos`, if it can be paid for, see `pay_for_building`
+pub fn place_building(
+    commands: &mut Commands,
+    game: &mut Game,
+    ruleset: &Ruleset,
+    turns: &mut TurnState,
+    asset_index: &AssetIndex,
+    kind: BuildingType,
+    pos: BoardPos,
+) -> Option<Entity> {
+    // nothing gets paid for a building that can't be shown
+    let scene = match asset_index.scene_by_building.get(&kind) {
+        Some(scene) => scene.clone(),
+        None => {
+            warn!("place_building: no scene for a {:?}", kind);
+            return None;
+        }
+    };
+    if !pay_for_building(game, ruleset, turns, kind, pos) {
+        return None;
+    }
+
+    let mut transform = Transform::from_translation(game.cell_translation(pos));
+    transform.apply_non_uniform_scale(Vec3::splat(0.3));
+
+    let entity = commands
+        .spawn_bundle((transform, GlobalTransform::identity()))
+        .with_children(|parent| {
+            parent.spawn_scene(scene);
+        })
+        .insert(Building::new(kind, pos))
+        .id();
+    game.board[pos.j][pos.i].building = Some(entity);
+    Some(entity)
+}
+
+/// Buildings produce into the player's inventory over time
+pub fn building_production(clock: Res<SimClock>, mut game: ResMut<Game>, mut buildings: Query<&mut Building>) {
+    for mut building in buildings.iter_mut() {
+        building.produce(clock.delta, &mut game.player.inventory);
+    }
+}
+
+pub fn harvest_pop_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut pops: Query<(Entity, &mut HarvestPop, &mut Transform)>,
+) {
+    for (entity, mut pop, mut transform) in pops.iter_mut() {
+        pop.0.tick(time.delta());
+        transform.translation.y += time.delta_seconds();
+        if pop.0.finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
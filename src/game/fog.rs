@@ -0,0 +1,185 @@
+use bevy::prelude::*;
+
+use crate::game::{ Cell, Game };
+use crate::game::board::BoardPos;
+use crate::game::commons::{ Commons, lerp_color };
+use crate::hextiles::hex::HexCoord;
+
+/// How many cells away the player can see, terrain permitting
+pub const SIGHT_RADIUS: usize = 4;
+/// How far above its cell an actor's eyes are
+const EYE_HEIGHT: f32 = 0.1;
+/// What unexplored tiles fade into
+const FOG_COLOR: Color = Color::rgb(0.05, 0.05, 0.08);
+
+#[derive(Copy, Clone, Debug, PartialEq, Hash)]
+pub enum TileVisibility {
+    Unexplored,
+    /// Seen before, but not in sight right now
+    Explored,
+    Visible,
+}
+impl Eq for TileVisibility {}
+
+impl Default for TileVisibility {
+    fn default() -> Self {
+        TileVisibility::Unexplored
+    }
+}
+
+/// What the player has seen of the board, laid out like `Game::board`
+#[derive(Default)]
+pub struct FogOfWar {
+    pub tiles: Vec<Vec<TileVisibility>>,
+    /// Where the player stood when the fog was last lifted
+    pub origin: Option<BoardPos>,
+}
+
+impl FogOfWar {
+    pub fn visibility(&self, pos: BoardPos) -> TileVisibility {
+        self.tiles
+            .get(pos.j)
+            .and_then(|row| row.get(pos.i))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn is_visible(&self, pos: BoardPos) -> bool {
+        self.visibility(pos) == TileVisibility::Visible
+    }
+
+    /// Recompute what's in sight from `origin`, keeping everything seen before as explored
+    pub fn reveal(&mut self, board: &[Vec<Cell>], origin: BoardPos) {
+        let (size_i, size_j) = (board.first().map_or(0, |row| row.len()), board.len());
+        if self.tiles.len() != size_j || self.tiles.first().map_or(0, |row| row.len()) != size_i {
+            self.tiles = vec![vec![TileVisibility::Unexplored; size_i]; size_j];
+        }
+        for tile in self.tiles.iter_mut().flatten() {
+            if *tile == TileVisibility::Visible {
+                *tile = TileVisibility::Explored;
+            }
+        }
+        for pos in visible_cells(board, origin, SIGHT_RADIUS) {
+            self.tiles[pos.j][pos.i] = TileVisibility::Visible;
+        }
+        self.origin = Some(origin);
+    }
+}
+
+fn cell_at<'a>(board: &'a [Vec<Cell>], hex: &HexCoord) -> Option<&'a Cell> {
+    let (col, row) = hex.to_odd_r();
+    if col < 0 || row < 0 {
+        return None;
+    }
+    board.get(col as usize).and_then(|cells| cells.get(row as usize))
+}
+
+/// Whether `to` can be seen from `from`. Sight runs from the viewer's eyes down or up to the target cell,
+/// and is cut by any sight-blocking cell in between that rises up to the line.
+pub fn line_of_sight(board: &[Vec<Cell>], from: BoardPos, to: BoardPos) -> bool {
+    let (eye, target) = match (cell_at(board, &from.hex()), cell_at(board, &to.hex())) {
+        (Some(from_cell), Some(to_cell)) => (from_cell.height + EYE_HEIGHT, to_cell.height),
+        _ => return false,
+    };
+    let line = from.hex().line_to(&to.hex());
+    let steps = line.len() - 1;
+    line.iter()
+        .enumerate()
+        .skip(1)
+        .take(steps.saturating_sub(1))
+        .all(|(step, hex)| match cell_at(board, hex) {
+            Some(cell) => {
+                let sight = eye + (target - eye) * step as f32 / steps as f32;
+                !(cell.terrain.blocks_sight() && cell.height >= sight)
+            }
+            None => false,
+        })
+}
+
+/// Every cell within `radius` of `origin` that's in its line of sight
+pub fn visible_cells(board: &[Vec<Cell>], origin: BoardPos, radius: usize) -> Vec<BoardPos> {
+    let (size_i, size_j) = (board.first().map_or(0, |row| row.len()), board.len());
+    let mut visible = Vec::new();
+    for j in 0..size_j {
+        for i in 0..size_i {
+            let pos = BoardPos::new(i, j);
+            if origin.distance(&pos) <= radius && line_of_sight(board, origin, pos) {
+                visible.push(pos);
+            }
+        }
+    }
+    visible
+}
+
+/// The colour a tile of `color` is drawn in under the fog
+pub fn fog_tint(color: Color, visibility: TileVisibility) -> Color {
+    match visibility {
+        TileVisibility::Visible => color,
+        TileVisibility::Explored => lerp_color(FOG_COLOR, color, 0.5),
+        TileVisibility::Unexplored => lerp_color(FOG_COLOR, color, 0.1),
+    }
+}
+
+// lift the fog around the player whenever they moved
+pub fn update_fog(game: Res<Game>, mut fog: ResMut<FogOfWar>, mut commons: ResMut<Commons>) {
+    let origin = BoardPos::new(game.player.i, game.player.j);
+    if fog.origin == Some(origin) {
+        return;
+    }
+    fog.reveal(&game.board, origin);
+    // tiles are recoloured together with their stock
+    commons.dirty = true;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::components::Terrain;
+
+    fn board(size: usize, terrain: Terrain, height: f32) -> Vec<Vec<Cell>> {
+        (0..size)
+            .map(|_| {
+                (0..size)
+                    .map(|_| Cell { height, terrain, building: None, entity: None, stock: 0.0 })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn water_and_grass_do_not_block() {
+        let mut board = board(7, Terrain::Grass, 0.1);
+        board[3][4].terrain = Terrain::Water;
+        board[3][4].height = 0.05;
+        let origin = BoardPos::new(3, 3);
+        assert_eq!(board.len() * board.len(), visible_cells(&board, origin, 10).len());
+    }
+
+    #[test]
+    fn hills_block_unless_looked_over() {
+        let mut board = board(7, Terrain::Grass, 0.1);
+        // all on the same row, with the hill right in between
+        let (origin, hill, behind) = (BoardPos::new(3, 1), BoardPos::new(3, 3), BoardPos::new(3, 5));
+        board[hill.j][hill.i].terrain = Terrain::Hills;
+        board[hill.j][hill.i].height = 0.3;
+        assert!(line_of_sight(&board, origin, hill));
+        assert!(!line_of_sight(&board, origin, behind));
+
+        // looking down from higher up, the view clears the hill
+        board[origin.j][origin.i].height = 0.6;
+        assert!(line_of_sight(&board, origin, behind));
+    }
+
+    #[test]
+    fn reveal_keeps_explored_tiles() {
+        let board = board(12, Terrain::Grass, 0.1);
+        let mut fog = FogOfWar::default();
+        fog.reveal(&board, BoardPos::new(0, 0));
+        assert!(fog.is_visible(BoardPos::new(0, 0)));
+        assert_eq!(TileVisibility::Unexplored, fog.visibility(BoardPos::new(11, 11)));
+
+        fog.reveal(&board, BoardPos::new(11, 11));
+        assert_eq!(TileVisibility::Explored, fog.visibility(BoardPos::new(0, 0)));
+        assert!(fog.is_visible(BoardPos::new(11, 11)));
+    }
+}
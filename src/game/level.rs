@@ -0,0 +1,199 @@
+use bevy::asset::LoadState;
+use bevy::prelude::*;
+
+use crate::assets::AssetIndex;
+use crate::game::GameState;
+use crate::game::account::Profiles;
+use crate::game::components::TileType;
+use crate::game::player::CharacterType;
+use crate::ui::FontType;
+
+/// How often a stage is retried before the level is given up on
+pub const MAX_ATTEMPTS: u32 = 10;
+const RETRY_SECONDS: f32 = 0.5;
+
+/// The steps of building a level, in the order they run
+#[derive(Copy, Clone, Debug, PartialEq, Hash)]
+pub enum SetupStage {
+    Assets,
+    Board,
+    Entities,
+    Ui,
+    Done,
+    Failed,
+}
+impl Eq for SetupStage {}
+
+impl SetupStage {
+    pub fn next(self) -> SetupStage {
+        match self {
+            SetupStage::Assets => SetupStage::Board,
+            SetupStage::Board => SetupStage::Entities,
+            SetupStage::Entities => SetupStage::Ui,
+            SetupStage::Ui => SetupStage::Done,
+            SetupStage::Done => SetupStage::Done,
+            SetupStage::Failed => SetupStage::Failed,
+        }
+    }
+}
+
+/// Progress of the level currently being built. Every setup system only runs while its own stage is current,
+/// and either advances the pipeline, asks for a retry or fails it.
+pub struct LevelSetup {
+    pub stage: SetupStage,
+    /// Attempts made at the current stage
+    pub attempts: u32,
+    /// Why the last attempt didn't go through
+    pub error: Option<String>,
+    retry_timer: Timer,
+    waiting: bool,
+}
+
+impl Default for LevelSetup {
+    fn default() -> Self {
+        LevelSetup {
+            stage: SetupStage::Assets,
+            attempts: 0,
+            error: None,
+            retry_timer: Timer::from_seconds(RETRY_SECONDS, false),
+            waiting: false,
+        }
+    }
+}
+
+impl LevelSetup {
+    /// Whether the systems of `stage` should do their work this frame
+    pub fn is_running(&self, stage: SetupStage) -> bool {
+        self.stage == stage && !self.waiting
+    }
+
+    pub fn advance(&mut self) {
+        info!("LevelSetup: {:?} done", self.stage);
+        self.stage = self.stage.next();
+        self.attempts = 0;
+        self.error = None;
+    }
+
+    /// Try the current stage again a bit later, or give up after `MAX_ATTEMPTS`
+    pub fn retry(&mut self, reason: String) {
+        self.attempts += 1;
+        if self.attempts >= MAX_ATTEMPTS {
+            self.fail(reason);
+            return;
+        }
+        warn!("LevelSetup: {:?} attempt {} failed: {}", self.stage, self.attempts, reason);
+        self.error = Some(reason);
+        self.waiting = true;
+        self.retry_timer.reset();
+    }
+
+    pub fn fail(&mut self, reason: String) {
+        error!("LevelSetup: {:?} failed: {}", self.stage, reason);
+        self.stage = SetupStage::Failed;
+        self.error = Some(reason);
+    }
+}
+
+pub fn start_level_setup(mut setup: ResMut<LevelSetup>) {
+    *setup = LevelSetup::default();
+}
+
+fn load_state(asset_server: &AssetServer, name: &str, handle: Option<HandleUntyped>) -> Result<bool, String> {
+    match handle.map(|handle| asset_server.get_load_state(handle)) {
+        None => Err(format!("{} is not in the asset index", name)),
+        Some(LoadState::Failed) => Err(format!("{} failed to load", name)),
+        Some(LoadState::Loaded) => Ok(true),
+        Some(_) => Ok(false),
+    }
+}
+
+// everything the level needs has to be loaded before anything is spawned
+pub fn check_level_assets(
+    mut setup: ResMut<LevelSetup>,
+    asset_server: Res<AssetServer>,
+    asset_index: Res<AssetIndex>,
+    profiles: Res<Profiles>,
+) {
+    if !setup.is_running(SetupStage::Assets) {
+        return;
+    }
+
+    let avatar = asset_index
+        .mesh_by_type
+        .get(&profiles.account.avatar)
+        .or_else(|| asset_index.mesh_by_type.get(&CharacterType::Bunny));
+    let required = vec![
+        ("player mesh", avatar.map(|h| h.clone_untyped())),
+        ("NPC mesh", asset_index.mesh_by_type.get(&CharacterType::Monkey).map(|h| h.clone_untyped())),
+        ("cake scene", asset_index.scene_by_type.get(&TileType::Cake).map(|h| h.clone_untyped())),
+        ("main font", asset_index.font_by_type.get(&FontType::Main).map(|h| h.clone_untyped())),
+    ];
+
+    let mut pending = Vec::new();
+    for (name, handle) in required {
+        match load_state(&asset_server, name, handle) {
+            Ok(true) => (),
+            Ok(false) => pending.push(name),
+            // a missing or broken asset won't fix itself
+            Err(reason) => return setup.fail(reason),
+        }
+    }
+
+    if pending.is_empty() {
+        setup.advance();
+    } else {
+        setup.retry(format!("still loading {}", pending.join(", ")));
+    }
+}
+
+// start the game once the pipeline went through, or back out to the menu with the error
+pub fn finish_level_setup(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut setup: ResMut<LevelSetup>,
+    mut state: ResMut<State<GameState>>,
+    entities: Query<Entity, Without<Camera>>,
+) {
+    if setup.waiting && setup.retry_timer.tick(time.delta()).finished() {
+        setup.waiting = false;
+    }
+
+    match setup.stage {
+        SetupStage::Done => state.set(GameState::Playing).unwrap(),
+        SetupStage::Failed => {
+            // don't leave half a level behind
+            for entity in entities.iter() {
+                commands.entity(entity).despawn_recursive();
+            }
+            state.set(GameState::MainMenu).unwrap();
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stages_advance_in_order_and_retries_give_up() {
+        let mut setup = LevelSetup::default();
+        assert!(setup.is_running(SetupStage::Assets));
+
+        setup.retry(String::from("still loading"));
+        assert!(!setup.is_running(SetupStage::Assets));
+        assert_eq!(setup.attempts, 1);
+
+        setup.waiting = false;
+        setup.advance();
+        assert!(setup.is_running(SetupStage::Board));
+        assert_eq!(setup.attempts, 0);
+
+        for _ in 0..MAX_ATTEMPTS {
+            setup.waiting = false;
+            setup.retry(String::from("no board"));
+        }
+        assert_eq!(setup.stage, SetupStage::Failed);
+        assert_eq!(setup.error.as_deref(), Some("no board"));
+    }
+}
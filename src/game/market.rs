@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::api::watch::MarketWatch;
+use crate::config::market::{ MarketConfig, MarketRule, MarketTarget };
+use crate::game::bonus::BonusTimer;
+use crate::game::movement::StepCooldown;
+use crate::game::replay::{ Replay, SimClock, level_at };
+
+/// How far the water rises, in world units, for every bit its level goes above 1
+const WATER_RISE: f32 = 0.3;
+
+/// Slow at both ends, fast in the middle, for `t` from 0 to 1
+pub fn ease_in_out(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// A level that glides to where it's told to go instead of jumping there
+#[derive(Clone, Debug, PartialEq)]
+pub struct Eased {
+    from: f32,
+    to: f32,
+    elapsed: f32,
+    duration: f32,
+}
+
+impl Eased {
+    pub fn new(level: f32) -> Self {
+        Eased { from: level, to: level, elapsed: 0.0, duration: 0.0 }
+    }
+
+    pub fn value(&self) -> f32 {
+        if self.elapsed >= self.duration {
+            return self.to;
+        }
+        self.from + (self.to - self.from) * ease_in_out(self.elapsed / self.duration)
+    }
+
+    /// Head for `to` over `duration` seconds, starting from wherever the level is now
+    pub fn set_target(&mut self, to: f32, duration: f32) {
+        if to == self.to {
+            return;
+        }
+        self.from = self.value();
+        self.to = to;
+        self.elapsed = 0.0;
+        self.duration = duration;
+    }
+
+    pub fn advance(&mut self, seconds: f32) {
+        self.elapsed += seconds;
+    }
+}
+
+/// Where the market rules have pushed the game, as levels of 1 when nothing moved
+#[derive(Default)]
+pub struct MarketEffects {
+    rules: Vec<(MarketRule, Eased)>,
+    /// The first price seen of every symbol, what the rules measure moves against
+    reference: HashMap<String, f64>,
+}
+
+impl MarketEffects {
+    pub fn new(rules: &[MarketRule]) -> Self {
+        MarketEffects {
+            rules: rules.iter().map(|rule| (rule.clone(), Eased::new(1.0))).collect(),
+            reference: HashMap::new(),
+        }
+    }
+
+    /// Let `seconds` pass and steer every rule towards the latest prices
+    pub fn update(&mut self, watch: &MarketWatch, seconds: f32) {
+        for (rule, level) in self.rules.iter_mut() {
+            level.advance(seconds);
+            let price = match watch.price(&rule.symbol) {
+                Some(price) => price,
+                None => continue,
+            };
+            let reference = *self.reference.entry(rule.symbol.clone()).or_insert(price);
+            let change = ((price / reference - 1.0) * 100.0) as f32;
+            level.set_target(rule.level(change), rule.ease_seconds);
+        }
+    }
+
+    /// Every rule on `target` together, 1 if none has a say
+    pub fn level(&self, target: MarketTarget) -> f32 {
+        self.rules
+            .iter()
+            .filter(|(rule, _)| rule.target == target)
+            .map(|(_, level)| level.value())
+            .product()
+    }
+
+    /// How far above its usual height the water stands, below it when the level drops
+    pub fn water_rise(&self) -> f32 {
+        (self.level(MarketTarget::WaterLevel) - 1.0) * WATER_RISE
+    }
+}
+
+// every level starts out calm, at the prices it's started on
+pub fn setup_market_effects(config: Res<MarketConfig>, mut effects: ResMut<MarketEffects>) {
+    *effects = MarketEffects::new(&config.rules);
+}
+
+pub fn update_market_effects(time: Res<Time>, watch: Res<MarketWatch>, mut effects: ResMut<MarketEffects>) {
+    effects.update(&watch, time.delta_seconds());
+}
+
+/// The recorded level on `tick` when `playing`, otherwise `live`, noted down in `changes` if it moved
+fn recorded_level(changes: &mut Vec<(u64, f32)>, playing: bool, tick: u64, live: f32) -> f32 {
+    if playing {
+        return level_at(changes, tick);
+    }
+    if live != level_at(changes, tick) {
+        changes.push((tick, live));
+    }
+    live
+}
+
+// the bonus rate changes when bonuses turn up, so it's recorded with the game like the frame times are
+pub fn market_bonus_rate(
+    clock: Res<SimClock>,
+    effects: Res<MarketEffects>,
+    mut replay: ResMut<Replay>,
+    mut timer: ResMut<BonusTimer>,
+) {
+    if !clock.running {
+        return;
+    }
+    let playing = replay.playing;
+    let rate = recorded_level(&mut replay.recording.bonus_rates, playing, clock.tick, effects.level(MarketTarget::BonusRate));
+    timer.set_rate(rate);
+}
+
+// the player's speed changes which steps it gets to take, so it's recorded like the bonus rate
+pub fn market_player_speed(
+    clock: Res<SimClock>,
+    effects: Res<MarketEffects>,
+    mut replay: ResMut<Replay>,
+    mut cooldown: ResMut<StepCooldown>,
+) {
+    if !clock.running {
+        return;
+    }
+    let playing = replay.playing;
+    let speed = recorded_level(&mut replay.recording.player_speeds, playing, clock.tick, effects.level(MarketTarget::PlayerSpeed));
+    cooldown.set_speed(speed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::feed::{ CsvFeed, PriceFeed, RandomWalkFeed, fetch_all };
+
+    const PRICES: &str = "time,symbol,price\n0,HOTUSDT,1.0\n1,HOTUSDT,1.1\n2,HOTUSDT,0.5\n";
+
+    fn poll(feed: &mut dyn PriceFeed, watch: &mut MarketWatch) {
+        for (symbol, price, change) in fetch_all(feed, &watch.symbols.clone()).unwrap() {
+            watch.update(&symbol, price, change);
+        }
+    }
+
+    #[test]
+    fn eased_levels_glide_from_where_they_are() {
+        assert_eq!((0.0, 0.5, 1.0), (ease_in_out(0.0), ease_in_out(0.5), ease_in_out(1.0)));
+
+        let mut level = Eased::new(1.0);
+        level.set_target(2.0, 4.0);
+        let mut last = level.value();
+        for _ in 0..4 {
+            level.advance(0.5);
+            assert!(level.value() > last);
+            last = level.value();
+        }
+        assert_eq!(1.5, level.value());
+
+        // turning back midway starts from there, not from the old target
+        level.set_target(1.0, 2.0);
+        assert_eq!(1.5, level.value());
+        level.advance(2.0);
+        assert_eq!(1.0, level.value());
+    }
+
+    #[test]
+    fn rules_follow_the_price_within_their_bounds() {
+        let rules = vec![
+            MarketRule::new("HOTUSDT", MarketTarget::BonusRate, 0.1, 0.5, 2.0, 1.0),
+            MarketRule::new("HOTUSDT", MarketTarget::PlayerSize, 0.05, 0.5, 2.0, 1.0),
+        ];
+        let mut effects = MarketEffects::new(&rules);
+        let mut watch = MarketWatch::new(&[String::from("HOTUSDT")]);
+        let mut feed = CsvFeed::parse(PRICES).unwrap();
+
+        // the first price is where every move is measured from
+        poll(&mut feed, &mut watch);
+        effects.update(&watch, 0.0);
+        assert_eq!(1.0, effects.level(MarketTarget::BonusRate));
+
+        // up 10%, eased in over a second
+        poll(&mut feed, &mut watch);
+        effects.update(&watch, 0.0);
+        effects.update(&watch, 0.5);
+        let halfway = effects.level(MarketTarget::BonusRate);
+        assert!(halfway > 1.0 && halfway < 2.0);
+        effects.update(&watch, 0.5);
+        assert!((effects.level(MarketTarget::BonusRate) - 2.0).abs() < 1e-5);
+        assert!((effects.level(MarketTarget::PlayerSize) - 1.5).abs() < 1e-5);
+
+        // down 50% bottoms out
+        poll(&mut feed, &mut watch);
+        effects.update(&watch, 0.0);
+        effects.update(&watch, 1.0);
+        assert_eq!(0.5, effects.level(MarketTarget::BonusRate));
+        assert_eq!(1.0, effects.level(MarketTarget::PlayerSpeed));
+        assert_eq!(0.0, effects.water_rise());
+    }
+
+    #[test]
+    fn random_walk_stays_in_bounds() {
+        let config = MarketConfig::default();
+        let mut effects = MarketEffects::new(&config.rules);
+        let mut watch = MarketWatch::new(&config.watched());
+        let mut feed = RandomWalkFeed::new(7, 1.0, 0.05);
+        for _ in 0..500 {
+            poll(&mut feed, &mut watch);
+            effects.update(&watch, 0.1);
+            for rule in config.rules.iter() {
+                let level = effects.level(rule.target);
+                assert!(level >= rule.min && level <= rule.max, "{:?} at {}", rule.target, level);
+            }
+        }
+    }
+}
@@ -3,22 +3,47 @@ pub mod board;
 pub mod bonus;
 pub mod player;
 pub mod account;
+pub mod ai;
+pub mod turns;
+pub mod economy;
+pub mod commons;
+pub mod scores;
+pub mod level;
+pub mod fog;
+pub mod replay;
+pub mod movement;
+pub mod market;
 
 use bevy::prelude::{ Color, Entity, Handle, Scene, Vec3 };
 
+use board::BoardPos;
+use components::Terrain;
+use economy::Inventory;
+use crate::hextiles::tile_center;
+
 pub struct Cell {
     pub height: f32,
+    pub terrain: Terrain,
+    pub building: Option<Entity>,
+    /// The tile entity drawing this cell
+    pub entity: Option<Entity>,
+    /// Renewable resource left on the cell, see `commons`
+    pub stock: f32,
 }
 
 #[derive(Default)]
 pub struct BoardParams {
     pub size_x: usize,
-    pub size_y: usize
+    pub size_y: usize,
+    /// Seeds the terrain generation, so a board can be played again
+    pub seed: u64,
 }
 
 pub struct BoardColors {
     // pub colors: Vec<Color>
-    pub colors: [bevy::prelude::Color; 3]
+    pub colors: [bevy::prelude::Color; 3],
+    /// What a tile fades to once its stock is used up
+    pub depleted: bevy::prelude::Color,
 }
 
 #[derive(Default)]
@@ -26,6 +51,7 @@ pub struct Player {
     pub entity: Option<Entity>,
     pub i: usize,
     pub j: usize,
+    pub inventory: Inventory,
 }
 
 #[derive(Default)]
@@ -47,10 +73,31 @@ pub struct Game {
     pub camera_is_focus: Vec3,
 }
 
+impl Game {
+    /// The board dimensions as `(size_i, size_j)`, matching `board[j][i]`
+    pub fn board_size(&self) -> (usize, usize) {
+        (self.board.first().map_or(0, |row| row.len()), self.board.len())
+    }
+
+    /// Where an actor standing on `pos` is placed in the world, on the middle of its tile
+    pub fn cell_translation(&self, pos: BoardPos) -> Vec3 {
+        let height = self.board
+            .get(pos.j)
+            .and_then(|row| row.get(pos.i))
+            .map_or(0.0, |cell| cell.height);
+        tile_center(pos, height)
+    }
+}
+
 #[derive(Clone, Eq, PartialEq, Debug, Hash)]
 pub enum GameState {
     Loading,
+    MainMenu,
+    Settings,
+    Controls,
     FinishedLoading,
     Playing,
+    Paused,
     GameOver,
+    Leaderboard,
 }
\ No newline at end of file
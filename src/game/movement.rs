@@ -0,0 +1,368 @@
+use std::fmt;
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crate::game::Game;
+use crate::game::ai::Npc;
+use crate::game::board::{ BoardPos, find_path };
+use crate::game::components::Terrain;
+use crate::game::economy::{ Building, BuildingType };
+use crate::game::turns::{ Actor, Ruleset, TurnState };
+use crate::input::GameCommand;
+use crate::input::selection::Selection;
+
+/// Seconds a moved entity takes for every cell on its path
+const SECONDS_PER_STEP: f32 = 0.15;
+/// The bonus floats a bit above its cell, see `bonus::replace_bonus`
+const BONUS_HEIGHT: f32 = 0.2;
+/// Seconds the player waits between two steps, at the usual speed
+const STEP_COOLDOWN: f32 = 0.15;
+
+/// Holds the player back between steps, so a held key walks rather than spending every action point at once.
+/// Runs on the `SimClock`, so a replay holds back the same steps.
+pub struct StepCooldown {
+    remaining: Duration,
+    speed: f32,
+}
+
+impl Default for StepCooldown {
+    fn default() -> Self {
+        StepCooldown { remaining: Duration::ZERO, speed: 1.0 }
+    }
+}
+
+impl StepCooldown {
+    /// The player gets about `speed` times as fast as usual, see `market::market_player_speed`
+    pub fn set_speed(&mut self, speed: f32) {
+        // no speed at all would mean a wait that never ends
+        self.speed = speed.max(0.1);
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub fn tick(&mut self, delta: Duration) {
+        self.remaining = self.remaining.saturating_sub(delta);
+    }
+
+    pub fn ready(&self) -> bool {
+        self.remaining == Duration::ZERO
+    }
+
+    /// Start the wait for the next step
+    pub fn start(&mut self) {
+        self.remaining = Duration::from_secs_f32(STEP_COOLDOWN / self.speed);
+    }
+}
+
+/// Something on the board that can be picked up and moved with `GameCommand::SelectedEntityMove`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MoveSubject {
+    Player,
+    Bonus,
+    Building(Entity),
+}
+
+impl MoveSubject {
+    /// What stands on `pos` that could be moved. The player goes first, then buildings, then the bonus.
+    pub fn at(game: &Game, pos: BoardPos) -> Option<MoveSubject> {
+        if pos == BoardPos::new(game.player.i, game.player.j) {
+            return Some(MoveSubject::Player);
+        }
+        if let Some(building) = game.board.get(pos.j).and_then(|row| row.get(pos.i)).and_then(|cell| cell.building) {
+            return Some(MoveSubject::Building(building));
+        }
+        if game.bonus.entity.is_some() && pos == BoardPos::new(game.bonus.i, game.bonus.j) {
+            return Some(MoveSubject::Bonus);
+        }
+        None
+    }
+
+    pub fn entity(&self, game: &Game) -> Option<Entity> {
+        match self {
+            MoveSubject::Player => game.player.entity,
+            MoveSubject::Bonus => game.bonus.entity,
+            MoveSubject::Building(entity) => Some(*entity),
+        }
+    }
+
+    /// Whether the subject can pass or stay on `terrain`. `building` is the kind of a moved building.
+    fn can_enter(&self, terrain: Terrain, building: Option<BuildingType>) -> bool {
+        match self {
+            MoveSubject::Player => true,
+            // the cake doesn't float
+            MoveSubject::Bonus => terrain != Terrain::Water,
+            MoveSubject::Building(_) => building.map_or(false, |kind| kind.allowed_on(terrain)),
+        }
+    }
+
+    /// Cells the subject can neither pass nor end up on
+    fn blocked(&self, game: &Game, npcs: &[BoardPos]) -> Vec<BoardPos> {
+        let mut blocked = npcs.to_vec();
+        match self {
+            // the player walks onto the bonus to eat it, and can stand on buildings
+            MoveSubject::Player => (),
+            MoveSubject::Bonus => blocked.push(BoardPos::new(game.player.i, game.player.j)),
+            MoveSubject::Building(_) => {
+                blocked.clear();
+                for (j, row) in game.board.iter().enumerate() {
+                    for (i, cell) in row.iter().enumerate() {
+                        if cell.building.is_some() {
+                            blocked.push(BoardPos::new(i, j));
+                        }
+                    }
+                }
+            }
+        }
+        blocked
+    }
+}
+
+/// Why a move was turned down
+#[derive(Clone, Debug, PartialEq)]
+pub enum MoveError {
+    NothingSelected,
+    NothingToMove(BoardPos),
+    OffBoard(BoardPos),
+    Occupied(BoardPos),
+    Terrain(BoardPos, Terrain),
+    NoPath(BoardPos),
+    CantAfford(u32),
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MoveError::NothingSelected => write!(f, "nothing is selected"),
+            MoveError::NothingToMove(pos) => write!(f, "nothing to move on {:?}", pos),
+            MoveError::OffBoard(pos) => write!(f, "{:?} is off the board", pos),
+            MoveError::Occupied(pos) => write!(f, "{:?} is taken", pos),
+            MoveError::Terrain(pos, terrain) => write!(f, "can't go onto {:?} at {:?}", terrain, pos),
+            MoveError::NoPath(pos) => write!(f, "no way through to {:?}", pos),
+            MoveError::CantAfford(cost) => write!(f, "the way costs {} action points", cost),
+        }
+    }
+}
+
+/// The cells `subject` passes on its way from `from` to `to`, both ends included
+pub fn plan_move(
+    game: &Game,
+    subject: MoveSubject,
+    building: Option<BuildingType>,
+    from: BoardPos,
+    to: BoardPos,
+    npcs: &[BoardPos],
+) -> Result<Vec<BoardPos>, MoveError> {
+    let (size_i, size_j) = game.board_size();
+    let cell = game.board.get(to.j).and_then(|row| row.get(to.i)).ok_or(MoveError::OffBoard(to))?;
+    if !subject.can_enter(cell.terrain, building) {
+        return Err(MoveError::Terrain(to, cell.terrain));
+    }
+    let blocked = subject.blocked(game, npcs);
+    if to != from && blocked.contains(&to) {
+        return Err(MoveError::Occupied(to));
+    }
+    let passable = |pos: BoardPos| {
+        !blocked.contains(&pos) && subject.can_enter(game.board[pos.j][pos.i].terrain, building)
+    };
+    find_path(from, to, size_i, size_j, passable).ok_or(MoveError::NoPath(to))
+}
+
+/// Move whatever stands on the selected cell to `to`, paying for the player's steps in turn-based mode.
+/// In real time the player stays where it is, and walks the path afterwards, see `Route`.
+/// Returns what was moved, and along which path.
+pub fn move_selected(
+    game: &mut Game,
+    selection: &Selection,
+    building: Option<BuildingType>,
+    to: BoardPos,
+    npcs: &[BoardPos],
+    ruleset: &Ruleset,
+    turns: &mut TurnState,
+) -> Result<(MoveSubject, Vec<BoardPos>), MoveError> {
+    let from = *selection.cells.first().ok_or(MoveError::NothingSelected)?;
+    let subject = MoveSubject::at(game, from).ok_or(MoveError::NothingToMove(from))?;
+    let path = plan_move(game, subject, building, from, to, npcs)?;
+
+    match subject {
+        MoveSubject::Player => {
+            let cost = path.iter().skip(1).map(|pos| game.board[pos.j][pos.i].terrain.movement_cost()).sum();
+            if !turns.try_spend(ruleset, Actor::Player, cost) {
+                return Err(MoveError::CantAfford(cost));
+            }
+            if ruleset.is_turn_based() {
+                game.player.i = to.i;
+                game.player.j = to.j;
+            }
+        }
+        MoveSubject::Bonus => {
+            game.bonus.i = to.i;
+            game.bonus.j = to.j;
+        }
+        MoveSubject::Building(entity) => {
+            game.board[from.j][from.i].building = None;
+            game.board[to.j][to.i].building = Some(entity);
+        }
+    }
+    Ok((subject, path))
+}
+
+/// The cells the player has yet to walk in real time, one whenever the `StepCooldown` runs out.
+/// See `input::move_actors`.
+pub struct Route(pub Vec<BoardPos>);
+
+/// Slides an entity along the cells of a move
+pub struct PathMover {
+    pub waypoints: Vec<Vec3>,
+    /// Cells covered so far, fractions included
+    pub progress: f32,
+}
+
+// move whatever is selected, then keep it selected where it ended up. A player walking its route
+// in real time takes the selection along as it goes.
+pub fn selected_entity_move_commands(
+    mut commands: Commands,
+    mut game_commands: EventReader<GameCommand>,
+    mut game: ResMut<Game>,
+    mut selection: ResMut<Selection>,
+    ruleset: Res<Ruleset>,
+    mut turns: ResMut<TurnState>,
+    npcs: Query<&Npc>,
+    mut buildings: Query<&mut Building>,
+) {
+    for command in game_commands.iter() {
+        let to = match command {
+            GameCommand::SelectedEntityMove { to } => *to,
+            _ => continue,
+        };
+        let npc_cells: Vec<BoardPos> = npcs.iter().map(|npc| npc.pos).collect();
+        let building = selection
+            .cells
+            .first()
+            .and_then(|pos| MoveSubject::at(&game, *pos))
+            .and_then(|subject| match subject {
+                MoveSubject::Building(entity) => buildings.get_mut(entity).ok().map(|building| building.kind),
+                _ => None,
+            });
+
+        let (subject, path) = match move_selected(&mut game, &selection, building, to, &npc_cells, &ruleset, &mut turns) {
+            Ok(moved) => moved,
+            Err(e) => {
+                info!("selected_entity_move_commands: {}", e);
+                continue;
+            }
+        };
+        if let MoveSubject::Building(entity) = subject {
+            if let Ok(mut building) = buildings.get_mut(entity) {
+                building.pos = to;
+            }
+        }
+        let walks = subject == MoveSubject::Player && !ruleset.is_turn_based();
+        if let Some(entity) = subject.entity(&game) {
+            if walks {
+                commands.entity(entity).insert(Route(path.iter().skip(1).copied().collect()));
+            } else {
+                let lift = if subject == MoveSubject::Bonus { Vec3::Y * BONUS_HEIGHT } else { Vec3::ZERO };
+                let waypoints = path.iter().map(|pos| game.cell_translation(*pos) + lift).collect();
+                commands.entity(entity).insert(PathMover { waypoints, progress: 0.0 });
+            }
+        }
+        if !walks {
+            selection.select(vec![to], false);
+        }
+    }
+}
+
+// slide moved entities from cell to cell, the player as fast as the market lets it
+pub fn animate_moves(
+    mut commands: Commands,
+    time: Res<Time>,
+    game: Res<Game>,
+    cooldown: Res<StepCooldown>,
+    mut movers: Query<(Entity, &mut PathMover, &mut Transform)>,
+) {
+    for (entity, mut mover, mut transform) in movers.iter_mut() {
+        let speed = if Some(entity) == game.player.entity { cooldown.speed() } else { 1.0 };
+        mover.progress += time.delta_seconds() / SECONDS_PER_STEP * speed;
+        let last = mover.waypoints.len().saturating_sub(1);
+        let step = mover.progress.floor() as usize;
+        if step >= last {
+            if let Some(end) = mover.waypoints.last() {
+                transform.translation = *end;
+            }
+            commands.entity(entity).remove::<PathMover>();
+            continue;
+        }
+        let (a, b) = (mover.waypoints[step], mover.waypoints[step + 1]);
+        transform.translation = a.lerp(b, mover.progress.fract());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Cell;
+    use crate::game::turns::Ruleset;
+
+    fn game(size: usize) -> Game {
+        let mut game = Game::default();
+        game.board = (0..size)
+            .map(|_| {
+                (0..size)
+                    .map(|_| Cell { height: 0.1, terrain: Terrain::Grass, building: None, entity: None, stock: 0.0 })
+                    .collect()
+            })
+            .collect();
+        game
+    }
+
+    fn selected(pos: BoardPos) -> Selection {
+        let mut selection = Selection::default();
+        selection.select(vec![pos], false);
+        selection
+    }
+
+    #[test]
+    fn player_moves_along_a_path_around_npcs() {
+        let mut game = game(6);
+        let mut turns = TurnState::default();
+        let npc = BoardPos::new(1, 0);
+        let (subject, path) = move_selected(
+            &mut game, &selected(BoardPos::new(0, 0)), None, BoardPos::new(3, 0), &[npc], &Ruleset::REAL_TIME, &mut turns,
+        ).unwrap();
+        assert_eq!(MoveSubject::Player, subject);
+        assert_eq!(Some(&BoardPos::new(3, 0)), path.last());
+        assert!(!path.contains(&npc));
+        // in real time the path is walked afterwards
+        assert_eq!(BoardPos::new(0, 0), BoardPos::new(game.player.i, game.player.j));
+
+        assert_eq!(
+            Err(MoveError::Occupied(npc)),
+            move_selected(&mut game, &selected(BoardPos::new(0, 0)), None, npc, &[npc], &Ruleset::REAL_TIME, &mut turns),
+        );
+    }
+
+    #[test]
+    fn moves_respect_terrain_and_cost() {
+        let mut game = game(6);
+        game.bonus.entity = Some(Entity::new(1));
+        game.bonus.i = 2;
+        game.board[0][4].terrain = Terrain::Water;
+        let mut turns = TurnState::default();
+        assert_eq!(
+            Err(MoveError::Terrain(BoardPos::new(4, 0), Terrain::Water)),
+            move_selected(&mut game, &selected(BoardPos::new(2, 0)), None, BoardPos::new(4, 0), &[], &Ruleset::REAL_TIME, &mut turns),
+        );
+
+        // three action points don't get the player across the board
+        game.board[0][4].terrain = Terrain::Grass;
+        let mut turns = TurnState::new(&Ruleset::TURN_BASED, std::iter::empty());
+        assert_eq!(
+            Err(MoveError::CantAfford(5)),
+            move_selected(&mut game, &selected(BoardPos::new(0, 0)), None, BoardPos::new(5, 0), &[], &Ruleset::TURN_BASED, &mut turns),
+        );
+        assert_eq!(BoardPos::new(0, 0), BoardPos::new(game.player.i, game.player.j));
+    }
+}
@@ -1,13 +1,17 @@
 
 use bevy::prelude::*;
 use bevy_mod_picking::{ PickableBundle, BoundVol };
+use serde::{ Deserialize, Serialize };
 
-use crate::SystemsLoaded;
-use crate::game::{ Game };
-use crate::api::binance::{ HotPrice };
+use crate::game::{ BoardParams, Game };
+use crate::game::board::BoardPos;
+use crate::config::market::MarketTarget;
+use crate::game::market::MarketEffects;
 use crate::assets::{ AssetIndex };
+use crate::game::account::{ Profiles };
+use crate::game::level::{ LevelSetup, SetupStage };
 
-#[derive(Copy, Clone, Debug, PartialEq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Hash, Serialize, Deserialize)]
 // TODO: Tease out the (logical) levels - graphical representation vs. the conceptual / game logic level.
 pub enum CharacterType {
     Monkey,
@@ -17,22 +21,46 @@ pub enum CharacterType {
 }
 impl Eq for CharacterType {}
 
+impl CharacterType {
+    pub const ALL: [CharacterType; 4] = [CharacterType::Monkey, CharacterType::Bunny, CharacterType::Alien, CharacterType::Fox];
+
+    /// The next character in `ALL`, wrapping around
+    pub fn next(&self) -> Self {
+        let index = CharacterType::ALL.iter().position(|c| c == self).unwrap_or(0);
+        CharacterType::ALL[(index + 1) % CharacterType::ALL.len()]
+    }
+}
+
+/// How big the player is while the market sits still
+const PLAYER_SCALE: f32 = 0.2;
 
 pub fn spawn_player(
     mut commands: Commands,
     mut game: ResMut<Game>,
     asset_index: Res<AssetIndex>,
     mut materials: ResMut<Assets<StandardMaterial>>,
-    mut systems_loaded: ResMut<SystemsLoaded>
+    profiles: Res<Profiles>,
+    board_params: Res<BoardParams>,
+    mut setup: ResMut<LevelSetup>
     ) {
+    if !setup.is_running(SetupStage::Entities) {
+        return;
+    }
+
+    // every level starts in the middle of the board
+    game.player.i = board_params.size_x / 2;
+    game.player.j = board_params.size_y / 2;
     
+    // Not every avatar has a mesh yet, fall back to the bunny
     if let Some(player_mesh) = asset_index
     .mesh_by_type
-    .get(&CharacterType::Bunny) {
+    .get(&profiles.account.avatar)
+    .or_else(|| asset_index.mesh_by_type.get(&CharacterType::Bunny)) {
 
-        let mut character_transform = Transform::from_xyz(9.0, 0.8, 8.0); 
+        let start = BoardPos::new(game.player.i, game.player.j);
+        let mut character_transform = Transform::from_translation(game.cell_translation(start));
         // character_transform.apply_non_uniform_scale(Vec3::new(0.1, 0.1, 0.1));
-        character_transform.apply_non_uniform_scale(Vec3::new(0.2, 0.2, 0.2));
+        character_transform.apply_non_uniform_scale(Vec3::splat(PLAYER_SCALE));
         // apply_non_uniform_scale(Vec3::new(0.1, 0.1, 0.1));
     
         game.player.entity = Some(    
@@ -48,53 +76,20 @@ pub fn spawn_player(
             .id()
         );        
     } else {
-        println!("spawn_player: Player mesh asset not loaded yet.");
-        // TODO: Do something to retry.
+        // The assets stage checked for it, so something unloaded it since
+        setup.fail(String::from("spawn_player: Player mesh asset missing."));
     }
-    
-    systems_loaded.player = true;
 }
 
+// the player grows and shrinks as the market rules say, see `market::MarketEffects`
 pub(crate) fn inflate_player_by_price(
-    price: Res<HotPrice>,
+    effects: Res<MarketEffects>,
     game: Res<Game>,
-    time: Res<Time>,
     mut transforms: Query<&mut Transform>,
 ) {
     if let Some(entity) = game.player.entity {
         if let Ok(mut player_transform) = transforms.get_mut(entity) {
-            // player_transform.rotate(Quat::from_rotation_y(time.delta_seconds()));
-            let price_modulated_last: f32 =
-                (((price.actual * 10000.0) % 1.0).powf(3.0) * 500.0) as f32;
-            let price_modulated_actual: f32 =
-                (((price.actual * 10000.0) % 1.0).powf(3.0) * 10.0) as f32;
-
-            // entity.
-            player_transform.scale = Vec3::splat(0.2);
-            /* Vec3::splat(
-                price_modulated_actual, // * time.seconds_since_startup().sin() as f32).abs(),
-            ); */
-            
-            /* player_transform.scale.lerp(
-                Vec3::splat(price_modulated_actual + 0.1),
-                1.0 // time.seconds_since_startup() as f32,
-            ); */
-
-            /* player_transform.scale = Vec3::splat(
-                    price_modulated_actual, // * time.seconds_since_startup().sin() as f32).abs(),
-                );
-            } */
-            /*  .ease_to(
-                    Sprite {
-                        size: Vec2::new(100., 100.),
-                        ..Default::default()
-                    },
-                    EaseFunction::QuadraticIn,
-                    EasingType::PingPong {
-                        duration: std::time::Duration::from_secs(1),
-                        pause: std::time::Duration::from_millis(500),
-                    },
-            */
+            player_transform.scale = Vec3::splat(PLAYER_SCALE * effects.level(MarketTarget::PlayerSize));
         }
     }
-}
\ No newline at end of file
+}
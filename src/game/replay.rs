@@ -0,0 +1,555 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{ Hash, Hasher };
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::scene::SceneSpawner;
+use rand::{ SeedableRng, rngs::StdRng };
+use serde::{ Deserialize, Serialize };
+
+use crate::assets::AssetIndex;
+use crate::game::{ BoardParams, Cell, Game, GameState };
+use crate::game::ai::{ BehaviourType, Difficulty, NpcSettings, npc_step, place_npcs };
+use crate::game::board::BoardPos;
+use crate::game::commons::{ Commons, HarvestLedger, regrow_commons };
+use crate::game::economy::{ BuildingType, building_production, economy_commands };
+use crate::game::bonus::{ BonusTimer, bonus_timer_finished, spawn_bonus };
+use crate::game::market::{ MarketEffects, market_bonus_rate, market_player_speed };
+use crate::game::turns::{ Ruleset, TurnState, end_turn_commands, expire_bonus, setup_turns };
+use crate::hextiles::sample_board;
+use crate::game::movement::{ StepCooldown, selected_entity_move_commands };
+use crate::input::{ GameCommand, move_actors };
+use crate::input::selection::{ Selection, selection_commands };
+use crate::storage;
+
+/// Where the last game played is kept
+pub const REPLAY_FILE: &str = "last_replay.ron";
+
+/// Every random draw gameplay makes comes from here. It's seeded with the level, so a replay draws the same.
+pub struct GameRng(pub StdRng);
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        GameRng(StdRng::seed_from_u64(seed))
+    }
+}
+
+impl Default for GameRng {
+    fn default() -> Self {
+        GameRng::new(0)
+    }
+}
+
+/// Game time. Ticks once per frame while playing, by the frame time or, in a replay, by the recorded one.
+/// Gameplay timers run on `delta` instead of `Time`, so they fire on the same ticks when replayed.
+#[derive(Default)]
+pub struct SimClock {
+    pub tick: u64,
+    pub delta: Duration,
+    /// Whether this frame counts as a tick
+    pub running: bool,
+}
+
+/// How a game ended, to tell whether a replay went the same way
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Outcome {
+    pub score: i32,
+    pub cake_eaten: u32,
+    pub player: BoardPos,
+    /// Digest of the terrain, see `board_digest`
+    pub board: u64,
+}
+
+impl Outcome {
+    pub fn of(game: &Game) -> Self {
+        Outcome {
+            score: game.score,
+            cake_eaten: game.cake_eaten,
+            player: BoardPos::new(game.player.i, game.player.j),
+            board: board_digest(&game.board),
+        }
+    }
+}
+
+/// Hash of the terrain and heights of a board. Stocks and buildings are left out, they only count
+/// through the turns and moves they change.
+pub fn board_digest(board: &[Vec<Cell>]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for row in board {
+        row.len().hash(&mut hasher);
+        for cell in row {
+            cell.terrain.hash(&mut hasher);
+            cell.height.to_bits().hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+/// Everything needed to play a game again: the level it was played on, the length of every tick,
+/// and the commands sent on each
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Recording {
+    pub seed: u64,
+    pub size_x: usize,
+    pub size_y: usize,
+    pub ruleset: String,
+    pub opponents: Vec<(BehaviourType, Difficulty)>,
+    /// Seconds of game time in every tick
+    pub deltas: Vec<f32>,
+    /// Commands by the tick they were sent on, in order
+    pub commands: Vec<(u64, GameCommand)>,
+    /// The bonus rate from every tick it changed on, see `market::market_bonus_rate`
+    #[serde(default)]
+    pub bonus_rates: Vec<(u64, f32)>,
+    /// The player's speed from every tick it changed on, see `market::market_player_speed`
+    #[serde(default)]
+    pub player_speeds: Vec<(u64, f32)>,
+    /// How the game ended, once it did
+    pub outcome: Option<Outcome>,
+}
+
+impl Recording {
+    pub fn new(board_params: &BoardParams, ruleset: &Ruleset, settings: &NpcSettings) -> Self {
+        Recording {
+            seed: board_params.seed,
+            size_x: board_params.size_x,
+            size_y: board_params.size_y,
+            ruleset: String::from(ruleset.name),
+            opponents: settings.opponents.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Read a recording from anywhere, not just the save directory
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        ron::de::from_str(&contents).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// How often bonuses came on `tick`, 1 for as often as usual
+    pub fn bonus_rate_at(&self, tick: u64) -> f32 {
+        level_at(&self.bonus_rates, tick)
+    }
+
+    /// How fast the player got about on `tick`, 1 for as fast as usual
+    pub fn player_speed_at(&self, tick: u64) -> f32 {
+        level_at(&self.player_speeds, tick)
+    }
+
+    pub fn board_params(&self) -> BoardParams {
+        BoardParams { size_x: self.size_x, size_y: self.size_y, seed: self.seed }
+    }
+
+    pub fn ruleset(&self) -> Result<Ruleset, String> {
+        Ruleset::by_name(&self.ruleset).ok_or_else(|| format!("unknown ruleset {}", self.ruleset))
+    }
+
+    /// Set the game up to be played like the recorded one
+    pub fn apply(&self, board_params: &mut BoardParams, ruleset: &mut Ruleset, settings: &mut NpcSettings) -> Result<(), String> {
+        *ruleset = self.ruleset()?;
+        *board_params = self.board_params();
+        settings.opponents = self.opponents.clone();
+        Ok(())
+    }
+
+    /// Compare `outcome` to the recorded one
+    pub fn check(&self, outcome: &Outcome) -> Result<(), String> {
+        match &self.outcome {
+            None => Err(String::from("the recording has no outcome")),
+            Some(recorded) if recorded != outcome => Err(format!("expected {:?}, got {:?}", recorded, outcome)),
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+/// The level last changed to on or before `tick`, 1 before the first change
+pub fn level_at(changes: &[(u64, f32)], tick: u64) -> f32 {
+    let changed = changes.partition_point(|(at, _)| *at <= tick);
+    changed.checked_sub(1).map_or(1.0, |index| changes[index].1)
+}
+
+/// The game being recorded, or the recording being played back
+#[derive(Default)]
+pub struct Replay {
+    pub recording: Recording,
+    /// Feeding `recording` back in, rather than recording
+    pub playing: bool,
+    /// Played back from the start of the next level
+    pub pending: bool,
+    /// How the last playback compared to the recording
+    pub verdict: Option<Result<(), String>>,
+    next_command: usize,
+}
+
+impl Replay {
+    pub fn play(recording: Recording) -> Self {
+        Replay { recording, playing: true, pending: true, ..Default::default() }
+    }
+}
+
+/// The systems that decide how a game goes, added to `set` in the order they run every tick: selection,
+/// moving the selection, the economy, NPCs picking their steps, everyone's moves, end of turn, bonus expiry.
+/// The app runs them while playing, and `simulate` runs the very same ones without rendering.
+pub fn gameplay_systems(set: SystemSet) -> SystemSet {
+    set.with_system(selection_commands.system().label("selection"))
+        .with_system(selected_entity_move_commands.system().label("entity_move").after("selection"))
+        .with_system(economy_commands.system().label("economy").after("entity_move"))
+        .with_system(building_production.system().after("economy"))
+        .with_system(regrow_commons.system().after("economy"))
+        .with_system(npc_step.system().label("npc_step").after("economy"))
+        .with_system(move_actors.system().label("actor_move").after("npc_step"))
+        .with_system(end_turn_commands.system().label("end_turn").after("actor_move"))
+        .with_system(expire_bonus.system().after("end_turn"))
+}
+
+/// A new bonus whenever the bonus timer runs out, after everyone moved
+pub fn bonus_systems() -> SystemSet {
+    SystemSet::new()
+        .with_run_criteria(bonus_timer_finished.system())
+        .with_system(spawn_bonus.system().after("actor_move"))
+}
+
+/// Play `recording` through without rendering, and return how it ended. The level is set up the way
+/// `spawn_player` and `spawn_npcs` do it, with bare entities in place of the drawn ones, and then the
+/// recording is fed through the same clock and gameplay systems a playback in the app goes through.
+pub fn simulate(recording: &Recording) -> Result<Outcome, String> {
+    let ruleset = recording.ruleset()?;
+    let board_params = recording.board_params();
+    if board_params.size_x == 0 || board_params.size_y == 0 {
+        return Err(format!("empty board {} x {}", board_params.size_x, board_params.size_y));
+    }
+
+    let mut world = World::default();
+    let mut game = Game::default();
+    game.board = sample_board(&board_params);
+    game.player.i = board_params.size_x / 2;
+    game.player.j = board_params.size_y / 2;
+    let start = BoardPos::new(game.player.i, game.player.j);
+    game.player.entity = Some(world.spawn().insert(Transform::from_translation(game.cell_translation(start))).id());
+    let mut rng = GameRng::new(recording.seed);
+    let settings = NpcSettings { opponents: recording.opponents.clone() };
+    for npc in place_npcs(&game, &settings, &mut rng.0) {
+        world.spawn().insert(Transform::from_translation(game.cell_translation(npc.pos))).insert(npc);
+    }
+
+    // nothing gets drawn, but buildings aren't placed without a scene to show
+    let mut asset_index = AssetIndex::default();
+    for kind in [BuildingType::House, BuildingType::PizzaShop].iter() {
+        asset_index.scene_by_building.insert(*kind, Handle::default());
+    }
+    let mut replay = Replay::play(recording.clone());
+    replay.pending = false;
+
+    world.insert_resource(game);
+    world.insert_resource(rng);
+    world.insert_resource(ruleset);
+    world.insert_resource(replay);
+    world.insert_resource(asset_index);
+    world.insert_resource(State::new(GameState::Playing));
+    world.insert_resource(Events::<GameCommand>::default());
+    world.insert_resource(Time::default());
+    world.insert_resource(SceneSpawner::default());
+    world.insert_resource(SimClock::default());
+    world.insert_resource(MarketEffects::default());
+    world.insert_resource(BonusTimer::default());
+    world.insert_resource(StepCooldown::default());
+    world.insert_resource(TurnState::default());
+    world.insert_resource(Selection::default());
+    world.insert_resource(Commons::default());
+    world.insert_resource(HarvestLedger::default());
+
+    // the stages of a frame in the app, with only what gameplay needs
+    let mut schedule = Schedule::default();
+    schedule.add_stage(CoreStage::First, SystemStage::single(Events::<GameCommand>::update_system.system()));
+    schedule.add_stage(
+        CoreStage::PreUpdate,
+        SystemStage::single_threaded()
+            .with_system(advance_sim_clock.system().label("sim_clock"))
+            .with_system(feed_replay_commands.system().after("sim_clock"))
+            .with_system(market_bonus_rate.system().after("sim_clock"))
+            .with_system(market_player_speed.system().after("sim_clock")),
+    );
+    schedule.add_stage(
+        CoreStage::Update,
+        SystemStage::single_threaded()
+            .with_system_set(State::<GameState>::get_driver())
+            .with_system_set(SystemSet::on_enter(GameState::Playing).with_system(setup_turns.system()))
+            .with_system_set(gameplay_systems(SystemSet::on_update(GameState::Playing)))
+            .with_system_set(bonus_systems()),
+    );
+    schedule.add_stage(CoreStage::PostUpdate, SystemStage::single(finish_sim_tick.system()));
+
+    // one frame past the recorded ones ends the game, if running out of score didn't already
+    for _ in 0..=recording.deltas.len() {
+        schedule.run(&mut world);
+        if *world.get_resource::<State<GameState>>().unwrap().current() != GameState::Playing {
+            break;
+        }
+    }
+    Ok(Outcome::of(world.get_resource::<Game>().unwrap()))
+}
+
+/// Simulate `recording` and check it ends the way it was recorded
+pub fn verify(recording: &Recording) -> Result<Outcome, String> {
+    let outcome = simulate(recording)?;
+    recording.check(&outcome)?;
+    Ok(outcome)
+}
+
+// a new level starts a new recording, unless a recording is about to be played back
+pub fn start_replay(
+    board_params: Res<BoardParams>,
+    ruleset: Res<Ruleset>,
+    settings: Res<NpcSettings>,
+    mut replay: ResMut<Replay>,
+    mut clock: ResMut<SimClock>,
+    mut rng: ResMut<GameRng>,
+) {
+    *clock = SimClock::default();
+    *rng = GameRng::new(board_params.seed);
+
+    if replay.pending {
+        replay.pending = false;
+        replay.next_command = 0;
+        replay.verdict = None;
+        info!("start_replay: playing back {} ticks", replay.recording.deltas.len());
+    } else {
+        replay.playing = false;
+        replay.recording = Recording::new(&board_params, &ruleset, &settings);
+    }
+}
+
+// hand over from the main menu to a replay that was asked for
+pub fn start_pending_replay(
+    mut state: ResMut<State<GameState>>,
+    mut board_params: ResMut<BoardParams>,
+    mut ruleset: ResMut<Ruleset>,
+    mut settings: ResMut<NpcSettings>,
+    mut replay: ResMut<Replay>,
+) {
+    if !replay.pending {
+        return;
+    }
+    if let Err(e) = replay.recording.apply(&mut board_params, &mut ruleset, &mut settings) {
+        error!("start_pending_replay: {}", e);
+        *replay = Replay::default();
+        return;
+    }
+    state.set(GameState::FinishedLoading).unwrap();
+}
+
+// tick the clock at the start of every frame spent playing, by the frame time or the recorded one
+pub fn advance_sim_clock(
+    time: Res<Time>,
+    mut state: ResMut<State<GameState>>,
+    mut clock: ResMut<SimClock>,
+    mut replay: ResMut<Replay>,
+) {
+    clock.running = *state.current() == GameState::Playing;
+    clock.delta = Duration::default();
+    if !clock.running {
+        return;
+    }
+
+    if !replay.playing {
+        replay.recording.deltas.push(time.delta_seconds());
+        clock.delta = Duration::from_secs_f32(time.delta_seconds());
+        return;
+    }
+    match replay.recording.deltas.get(clock.tick as usize) {
+        Some(delta) => clock.delta = Duration::from_secs_f32(*delta),
+        None => {
+            // the recorded game was left here
+            clock.running = false;
+            if let Err(e) = state.set(GameState::GameOver) {
+                warn!("advance_sim_clock: {:?}", e);
+            }
+        }
+    }
+}
+
+// send the recorded commands of this tick
+pub fn feed_replay_commands(
+    clock: Res<SimClock>,
+    mut replay: ResMut<Replay>,
+    mut game_commands: EventWriter<GameCommand>,
+) {
+    if !clock.running || !replay.playing {
+        return;
+    }
+    while let Some((tick, command)) = replay.recording.commands.get(replay.next_command) {
+        if *tick > clock.tick {
+            break;
+        }
+        game_commands.send(command.clone());
+        replay.next_command += 1;
+    }
+}
+
+// record what was sent during the tick, then move on to the next one
+pub fn finish_sim_tick(
+    mut clock: ResMut<SimClock>,
+    mut replay: ResMut<Replay>,
+    mut game_commands: EventReader<GameCommand>,
+) {
+    // read every frame, so nothing from a paused frame turns up later
+    let commands: Vec<GameCommand> = game_commands.iter().cloned().collect();
+    if !clock.running {
+        return;
+    }
+    if !replay.playing {
+        let tick = clock.tick;
+        replay.recording.commands.extend(
+            commands
+                .into_iter()
+                .filter(|command| command.affects_game() && !command.from_npc())
+                .map(|command| (tick, command)),
+        );
+    }
+    clock.tick += 1;
+}
+
+// once the game is over, keep the recording, or check the playback against it
+pub fn finish_replay(game: Res<Game>, mut replay: ResMut<Replay>) {
+    let outcome = Outcome::of(&game);
+    if replay.playing {
+        let verdict = replay.recording.check(&outcome);
+        match &verdict {
+            Ok(()) => info!("finish_replay: the replay ended as recorded"),
+            Err(e) => error!("finish_replay: the replay diverged: {}", e),
+        }
+        replay.verdict = Some(verdict);
+        return;
+    }
+
+    replay.recording.outcome = Some(outcome);
+    if let Err(e) = storage::save_ron(REPLAY_FILE, &replay.recording) {
+        warn!("finish_replay: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::turns::Actor;
+    use crate::input::{ MoveDirection, MoveTarget };
+
+    fn recording(ruleset: &Ruleset, opponents: Vec<(BehaviourType, Difficulty)>) -> Recording {
+        let board_params = BoardParams { size_x: 12, size_y: 10, seed: 42 };
+        let mut recording = Recording::new(&board_params, ruleset, &NpcSettings { opponents });
+        // a minute at 60 frames a second, walking around in circles
+        recording.deltas = vec![1.0 / 60.0; 3600];
+        let dirs = [MoveDirection::Up, MoveDirection::Right, MoveDirection::Down, MoveDirection::Left];
+        for tick in (0..3600).step_by(20) {
+            let dir = dirs[(tick / 200) % dirs.len()];
+            recording.commands.push((tick as u64, GameCommand::Move { actor: Actor::Player, to: MoveTarget::Dir(dir) }));
+            recording.commands.push((tick as u64, GameCommand::EndTurn));
+        }
+        recording
+    }
+
+    #[test]
+    fn same_recording_same_outcome() {
+        for ruleset in Ruleset::ALL.iter() {
+            let mut recording = recording(ruleset, NpcSettings::default().opponents);
+            recording.outcome = Some(simulate(&recording).unwrap());
+
+            // and again after a round trip through the replay file
+            let saved = ron::ser::to_string(&recording).unwrap();
+            let loaded: Recording = ron::de::from_str(&saved).unwrap();
+            assert_eq!(recording.outcome, verify(&loaded).ok());
+        }
+    }
+
+    #[test]
+    fn diverging_playback_is_caught() {
+        let mut recording = recording(&Ruleset::REAL_TIME, Vec::new());
+        recording.outcome = Some(simulate(&recording).unwrap());
+
+        recording.commands.retain(|(tick, _)| *tick < 1800);
+        assert!(verify(&recording).is_err());
+    }
+
+    #[test]
+    fn missed_bonuses_cost_score() {
+        let mut recording = recording(&Ruleset::REAL_TIME, Vec::new());
+        recording.commands.clear();
+        recording.deltas = vec![0.25; 240];
+        // standing still for a minute, every bonus but the last goes to waste
+        let outcome = simulate(&recording).unwrap();
+        assert_eq!(0, outcome.cake_eaten);
+        assert_eq!(-11 * 3, outcome.score);
+        assert_eq!(BoardPos::new(6, 5), outcome.player);
+    }
+
+    #[test]
+    fn harvests_cost_action_points_on_playback() {
+        let step = GameCommand::Move { actor: Actor::Player, to: MoveTarget::Dir(MoveDirection::Up) };
+        let mut recording = recording(&Ruleset::TURN_BASED, Vec::new());
+        recording.deltas = vec![1.0 / 60.0; 10];
+        recording.commands = vec![(0, step.clone())];
+        // any step on the board costs at most the three action points of a turn
+        assert_eq!(BoardPos::new(7, 5), simulate(&recording).unwrap().player);
+
+        // harvesting first uses them all up
+        recording.commands = vec![(0, GameCommand::Harvest), (0, GameCommand::Harvest), (0, GameCommand::Harvest), (0, step)];
+        recording.outcome = Some(simulate(&recording).unwrap());
+        assert_eq!(Some(BoardPos::new(6, 5)), recording.outcome.as_ref().map(|outcome| outcome.player));
+
+        let saved = ron::ser::to_string(&recording).unwrap();
+        assert!(verify(&ron::de::from_str(&saved).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn recorded_bonus_rate_is_played_back() {
+        let mut recording = recording(&Ruleset::REAL_TIME, Vec::new());
+        recording.commands.clear();
+        recording.deltas = vec![0.25; 240];
+        recording.bonus_rates = vec![(120, 2.0)];
+        assert_eq!(1.0, recording.bonus_rate_at(119));
+        assert_eq!(2.0, recording.bonus_rate_at(200));
+
+        // twice as many bonuses in the second half of the minute
+        let outcome = simulate(&recording).unwrap();
+        assert_eq!(-17 * 3, outcome.score);
+    }
+
+    #[test]
+    fn picked_moves_are_walked_a_cell_at_a_time() {
+        let start = BoardPos::new(6, 5);
+        let mut recording = recording(&Ruleset::REAL_TIME, Vec::new());
+        recording.deltas = vec![0.05; 4];
+        recording.commands = vec![
+            (0, GameCommand::Select { from: start, to: start, add: false }),
+            (0, GameCommand::SelectedEntityMove { to: BoardPos::new(9, 5) }),
+        ];
+        // the first cell right away, the next once the cooldown ran out
+        let player = simulate(&recording).unwrap().player;
+        assert_ne!(start, player);
+        assert_ne!(BoardPos::new(9, 5), player);
+
+        recording.deltas = vec![0.05; 60];
+        assert_eq!(BoardPos::new(9, 5), simulate(&recording).unwrap().player);
+    }
+
+    #[test]
+    fn recorded_player_speed_holds_steps_back() {
+        let step = GameCommand::Move { actor: Actor::Player, to: MoveTarget::Dir(MoveDirection::Up) };
+        let mut recording = recording(&Ruleset::REAL_TIME, Vec::new());
+        recording.deltas = vec![1.0 / 60.0; 30];
+        // a step every tenth of a second
+        recording.commands = (0..4).map(|index| (index * 6, step.clone())).collect();
+
+        // at the usual speed every other one comes too soon after the last
+        assert_eq!(BoardPos::new(8, 5), simulate(&recording).unwrap().player);
+
+        recording.player_speeds = vec![(0, 2.0)];
+        assert_eq!(2.0, recording.player_speed_at(0));
+        recording.outcome = Some(simulate(&recording).unwrap());
+        assert_eq!(Some(BoardPos::new(10, 5)), recording.outcome.as_ref().map(|outcome| outcome.player));
+
+        let saved = ron::ser::to_string(&recording).unwrap();
+        assert!(verify(&ron::de::from_str(&saved).unwrap()).is_ok());
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::{ Deserialize, Serialize };
+
+use crate::game::{ BoardParams, Game };
+use crate::game::account::Profiles;
+use crate::game::replay::Replay;
+use crate::game::turns::Ruleset;
+use crate::storage::{ self, StorageError };
+
+const HIGH_SCORES_FILE: &str = "highscores.ron";
+/// How many entries a single table keeps
+pub const TOP_N: usize = 10;
+const MAX_NAME_LEN: usize = 16;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScoreEntry {
+    pub name: String,
+    pub score: i32,
+    pub cake_eaten: u32,
+}
+
+/// Scores are only comparable when played by the same rules on the same board
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct BoardKey {
+    pub ruleset: String,
+    pub seed: u64,
+}
+
+impl BoardKey {
+    pub fn new(ruleset: &Ruleset, board_params: &BoardParams) -> Self {
+        BoardKey { ruleset: ruleset.name.to_string(), seed: board_params.seed }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct HighScoreTable {
+    pub tables: HashMap<BoardKey, Vec<ScoreEntry>>,
+}
+
+impl HighScoreTable {
+    /// Best first
+    pub fn top(&self, key: &BoardKey) -> &[ScoreEntry] {
+        self.tables.get(key).map(|entries| entries.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn qualifies(&self, key: &BoardKey, score: i32) -> bool {
+        let top = self.top(key);
+        top.len() < TOP_N || top.last().map_or(true, |last| score > last.score)
+    }
+
+    /// Insert `entry` in order, returning its rank if it made it into the table
+    pub fn insert(&mut self, key: BoardKey, entry: ScoreEntry) -> Option<usize> {
+        let entries = self.tables.entry(key).or_insert_with(Vec::new);
+        let rank = entries.iter().position(|e| entry.score > e.score).unwrap_or(entries.len());
+        if rank >= TOP_N {
+            return None;
+        }
+        entries.insert(rank, entry);
+        entries.truncate(TOP_N);
+        Some(rank)
+    }
+}
+
+/// Where the high score table is kept
+// TODO: A networked implementation, e.g. backed by a DNA.
+pub trait ScoreStorage: Send + Sync {
+    fn load(&self) -> Result<HighScoreTable, StorageError>;
+    fn save(&self, table: &HighScoreTable) -> Result<(), StorageError>;
+}
+
+/// Keeps the table in the local save directory
+pub struct LocalScoreStorage;
+
+impl ScoreStorage for LocalScoreStorage {
+    fn load(&self) -> Result<HighScoreTable, StorageError> {
+        Ok(storage::load_ron(HIGH_SCORES_FILE)?.unwrap_or_default())
+    }
+
+    fn save(&self, table: &HighScoreTable) -> Result<(), StorageError> {
+        storage::save_ron(HIGH_SCORES_FILE, table)
+    }
+}
+
+pub struct HighScores {
+    pub table: HighScoreTable,
+    pub storage: Box<dyn ScoreStorage>,
+}
+
+impl Default for HighScores {
+    fn default() -> Self {
+        HighScores { table: HighScoreTable::default(), storage: Box::new(LocalScoreStorage) }
+    }
+}
+
+/// The name being typed in on the game over screen
+#[derive(Default)]
+pub struct NameEntry {
+    pub active: bool,
+    pub name: String,
+}
+
+pub fn load_high_scores(mut high_scores: ResMut<HighScores>) {
+    match high_scores.storage.load() {
+        Ok(table) => high_scores.table = table,
+        Err(e) => warn!("load_high_scores: {}", e),
+    }
+}
+
+// ask for a name if the score made it into the table
+pub fn start_name_entry(
+    game: Res<Game>,
+    ruleset: Res<Ruleset>,
+    board_params: Res<BoardParams>,
+    high_scores: Res<HighScores>,
+    profiles: Res<Profiles>,
+    replay: Res<Replay>,
+    mut name_entry: ResMut<NameEntry>,
+) {
+    let key = BoardKey::new(&ruleset, &board_params);
+    name_entry.active = !replay.playing && high_scores.table.qualifies(&key, game.score);
+    name_entry.name = profiles.account.nickname.clone();
+}
+
+// type a name, Backspace to correct, Enter to submit
+pub fn name_entry_keyboard(
+    mut char_events: EventReader<ReceivedCharacter>,
+    keyboard_input: Res<Input<KeyCode>>,
+    game: Res<Game>,
+    ruleset: Res<Ruleset>,
+    board_params: Res<BoardParams>,
+    mut name_entry: ResMut<NameEntry>,
+    mut high_scores: ResMut<HighScores>,
+    mut profiles: ResMut<Profiles>,
+) {
+    if !name_entry.active {
+        return;
+    }
+    for event in char_events.iter() {
+        if !event.char.is_control() && name_entry.name.chars().count() < MAX_NAME_LEN {
+            name_entry.name.push(event.char);
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::Back) {
+        name_entry.name.pop();
+    }
+    if !keyboard_input.just_pressed(KeyCode::Return) || name_entry.name.trim().is_empty() {
+        return;
+    }
+
+    let name = name_entry.name.trim().to_string();
+    let entry = ScoreEntry { name: name.clone(), score: game.score, cake_eaten: game.cake_eaten };
+    let rank = high_scores.table.insert(BoardKey::new(&ruleset, &board_params), entry);
+    info!("name_entry_keyboard: {} ranked {:?}", name, rank);
+    if let Err(e) = high_scores.storage.save(&high_scores.table) {
+        warn!("name_entry_keyboard: {}", e);
+    }
+
+    profiles.account.nickname = name;
+    if let Err(e) = profiles.save() {
+        warn!("name_entry_keyboard: {}", e);
+    }
+    name_entry.active = false;
+}
@@ -0,0 +1,176 @@
+use bevy::prelude::*;
+use serde::{ Deserialize, Serialize };
+
+use crate::game::{ Game, GameState };
+use crate::game::ai::Npc;
+use crate::game::bonus::replace_bonus;
+use crate::game::replay::{ GameRng, SimClock };
+use crate::input::GameCommand;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum GameMode {
+    /// Everything runs every frame, bonuses are replaced on a timer
+    RealTime,
+    /// Actors take turns, spending action points on moves
+    TurnBased {
+        action_points: u32,
+        /// How many full rounds a bonus stays on the board
+        bonus_turns: u32,
+    },
+}
+
+/// The rules a level is played by
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ruleset {
+    pub name: &'static str,
+    pub mode: GameMode,
+}
+
+impl Ruleset {
+    pub const REAL_TIME: Ruleset = Ruleset {
+        name: "real-time",
+        mode: GameMode::RealTime,
+    };
+
+    pub const TURN_BASED: Ruleset = Ruleset {
+        name: "turn-based",
+        mode: GameMode::TurnBased { action_points: 3, bonus_turns: 4 },
+    };
+
+    pub const ALL: [Ruleset; 2] = [Ruleset::REAL_TIME, Ruleset::TURN_BASED];
+
+    pub fn by_name(name: &str) -> Option<Ruleset> {
+        Ruleset::ALL.iter().find(|ruleset| ruleset.name == name).cloned()
+    }
+
+    pub fn is_turn_based(&self) -> bool {
+        matches!(self.mode, GameMode::TurnBased { .. })
+    }
+}
+
+impl Default for Ruleset {
+    fn default() -> Self {
+        Ruleset::REAL_TIME
+    }
+}
+
+/// Whoever can take a turn
+#[derive(Copy, Clone, Debug, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Actor {
+    Player,
+    /// Entities only last as long as the game, so NPC commands are never saved, see `GameCommand::from_npc`
+    #[serde(skip)]
+    Npc(Entity),
+}
+impl Eq for Actor {}
+
+#[derive(Default)]
+pub struct TurnState {
+    pub turn: u32,
+    pub order: Vec<Actor>,
+    pub current: usize,
+    pub action_points: u32,
+    pub max_action_points: u32,
+    /// The turn in which the current bonus was put on the board
+    pub bonus_turn: u32,
+}
+
+impl TurnState {
+    /// The first turn of a level, with the player going first and the NPCs after them
+    pub fn new(ruleset: &Ruleset, npcs: impl Iterator<Item = Entity>) -> Self {
+        let action_points = match ruleset.mode {
+            GameMode::TurnBased { action_points, .. } => action_points,
+            GameMode::RealTime => 0,
+        };
+        let mut order = vec![Actor::Player];
+        order.extend(npcs.map(Actor::Npc));
+
+        TurnState {
+            turn: 1,
+            order,
+            current: 0,
+            action_points,
+            max_action_points: action_points,
+            bonus_turn: 1,
+        }
+    }
+
+    pub fn current_actor(&self) -> Option<Actor> {
+        self.order.get(self.current).copied()
+    }
+
+    /// Spend `cost` action points on behalf of `actor`. Always succeeds in real-time mode.
+    pub fn try_spend(&mut self, ruleset: &Ruleset, actor: Actor, cost: u32) -> bool {
+        if !ruleset.is_turn_based() {
+            return true;
+        }
+        if self.current_actor() != Some(actor) || self.action_points < cost {
+            return false;
+        }
+        self.action_points -= cost;
+        true
+    }
+
+    /// Hand over to the next actor, starting a new round after the last one
+    pub fn end_turn(&mut self) {
+        self.current += 1;
+        if self.current >= self.order.len() {
+            self.current = 0;
+            self.turn += 1;
+        }
+        self.action_points = self.max_action_points;
+    }
+
+    /// End the player's turn when they asked for it, or anybody's turn once they're out of action points
+    pub fn end_turn_if_done(&mut self, ruleset: &Ruleset, player_ended: bool) {
+        if !ruleset.is_turn_based() {
+            return;
+        }
+        let player_done = self.current_actor() == Some(Actor::Player) && player_ended;
+        if player_done || self.action_points == 0 {
+            self.end_turn();
+        }
+    }
+
+    /// Whether the bonus is due to be replaced, because it was eaten or has been around for too long
+    pub fn bonus_expired(&self, ruleset: &Ruleset, has_bonus: bool) -> bool {
+        match ruleset.mode {
+            GameMode::TurnBased { bonus_turns, .. } => !has_bonus || self.turn >= self.bonus_turn + bonus_turns,
+            GameMode::RealTime => false,
+        }
+    }
+}
+
+pub fn setup_turns(ruleset: Res<Ruleset>, mut turns: ResMut<TurnState>, npcs: Query<Entity, With<Npc>>) {
+    *turns = TurnState::new(&ruleset, npcs.iter());
+}
+
+/// End the player's turn when they ask for it, or anybody's turn once they're out of action points
+pub fn end_turn_commands(
+    ruleset: Res<Ruleset>,
+    mut game_commands: EventReader<GameCommand>,
+    mut turns: ResMut<TurnState>,
+) {
+    let end_turn = game_commands.iter().any(|command| *command == GameCommand::EndTurn);
+    turns.end_turn_if_done(&ruleset, end_turn);
+}
+
+/// Replace the bonus after it has been on the board for `bonus_turns` rounds, or once it was eaten
+pub fn expire_bonus(
+    mut state: ResMut<State<GameState>>,
+    mut commands: Commands,
+    mut game: ResMut<Game>,
+    mut rng: ResMut<GameRng>,
+    clock: Res<SimClock>,
+    ruleset: Res<Ruleset>,
+    mut turns: ResMut<TurnState>,
+) {
+    // only on frames the clock counts, so a replay draws the same bonuses
+    if !clock.running {
+        return;
+    }
+    if turns.bonus_expired(&ruleset, game.bonus.entity.is_some()) {
+        replace_bonus(&mut state, &mut commands, &mut game, &mut rng);
+        turns.bonus_turn = turns.turn;
+    }
+}
@@ -83,6 +83,54 @@ impl HexCoord {
         }
         NeighborIter { c: self, iter: DIRECTIONS.iter() }
     }
+
+    /// Convert "odd-r" offset coordinates (every odd row shifted right by half a hex),
+    /// which is the layout `geometry::center` produces, into a hex coordinate
+    pub fn from_odd_r(col: isize, row: isize) -> Self {
+        Self::new(col - (row - (row & 1)) / 2, row)
+    }
+
+    /// Convert back to "odd-r" offset coordinates, as `(col, row)`
+    pub fn to_odd_r(&self) -> (isize, isize) {
+        (self.q + (self.r - (self.r & 1)) / 2, self.r)
+    }
+
+    /// The number of steps between two coordinates
+    pub fn distance(&self, other: &HexCoord) -> usize {
+        let (dq, dr, ds) = ((self.q - other.q).abs(), (self.r - other.r).abs(), (self.s - other.s).abs());
+        dq.max(dr).max(ds) as usize
+    }
+
+    /// Every coordinate on the straight line to `other`, both ends included
+    pub fn line_to(&self, other: &HexCoord) -> Vec<HexCoord> {
+        let n = self.distance(other);
+        if n == 0 {
+            return vec![self.clone()];
+        }
+        // Nudge the line off the edges between two hexes, so ties always round the same way
+        let (q0, r0) = (self.q as f32 + 1e-4, self.r as f32 + 1e-4);
+        let (q1, r1) = (other.q as f32 + 1e-4, other.r as f32 + 1e-4);
+        (0..=n)
+            .map(|step| {
+                let t = step as f32 / n as f32;
+                Self::round(q0 + (q1 - q0) * t, r0 + (r1 - r0) * t)
+            })
+            .collect()
+    }
+
+    /// The hex containing the fractional coordinate `(q, r)`
+    fn round(q: f32, r: f32) -> Self {
+        let s = -q - r;
+        let (mut rq, mut rr, rs) = (q.round(), r.round(), s.round());
+        let (dq, dr, ds) = ((rq - q).abs(), (rr - r).abs(), (rs - s).abs());
+        // Whichever component rounded the furthest is recomputed from the other two
+        if dq > dr && dq > ds {
+            rq = -rr - rs;
+        } else if dr > ds {
+            rr = -rq - rs;
+        }
+        Self::new(rq as isize, rr as isize)
+    }
 }
 
 
@@ -112,4 +160,31 @@ mod tests {
             assert_eq!(point, neighbor.neighbor(dir.opposite()));
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn odd_r_roundtrip() {
+        for col in -5..5 {
+            for row in -5..5 {
+                let c = HexCoord::from_odd_r(col, row);
+                assert_eq!((col, row), c.to_odd_r());
+                assert_eq!(0, c.distance(&HexCoord::from_odd_r(col, row)));
+                for n in c.neighbors() {
+                    assert_eq!(1, c.distance(&n));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lines_are_contiguous() {
+        let a = HexCoord::new(-3, 1);
+        let b = HexCoord::new(4, -2);
+        let line = a.line_to(&b);
+        assert_eq!(a.distance(&b) + 1, line.len());
+        assert_eq!(Some(&a), line.first());
+        assert_eq!(Some(&b), line.last());
+        for pair in line.windows(2) {
+            assert_eq!(1, pair[0].distance(&pair[1]));
+        }
+    }
+}
@@ -4,18 +4,26 @@ use bevy::scene::Entity;
 use bevy::{ecs::system::EntityCommands, prelude::*};
 use bevy::render::pipeline::PrimitiveTopology;
 use bevy::render::mesh::Indices;
-use rand::Rng;
+use rand::{ Rng, SeedableRng, rngs::StdRng };
 
 use bevy_mod_picking::{BoundVol, PickableBundle};
 
 // Q: crate:: vs. super:: ?
 // use crate::{ BOARD_SIZE_I, BOARD_SIZE_J };
 use super::{ Game, Cell };
-use crate::{BoardColors, BoardParams, SystemsLoaded};
+use crate::game::components::Terrain;
+use crate::{BoardColors, BoardParams};
+use crate::game::board::BoardPos;
+use crate::game::level::{ LevelSetup, SetupStage };
+use crate::game::market::MarketEffects;
+use crate::input::selection::Tile;
 
 pub mod hex;
 mod geometry;
 
+/// Outer radius of a board tile
+const TILE_RADIUS: f32 = 1.0;
+
 
 pub fn sample_level(
     mut commands: Commands,
@@ -24,8 +32,15 @@ pub fn sample_level(
     board_params: Res<BoardParams>,
     board_colors: Res<BoardColors>,
     mut game: ResMut<Game>,
-    mut systems_loaded: ResMut<SystemsLoaded>
+    mut setup: ResMut<LevelSetup>
 ) {
+    if !setup.is_running(SetupStage::Board) {
+        return;
+    }
+    if board_params.size_x == 0 || board_params.size_y == 0 {
+        return setup.fail(format!("sample_level: empty board {} x {}", board_params.size_x, board_params.size_y));
+    }
+
     // add entities to the world
     /* commands
         // camera
@@ -43,68 +58,68 @@ pub fn sample_level(
     */
 
     let arc_commands = Arc::new(Mutex::new(commands));
-    game.board = Vec::new();
+    game.board = sample_board(&board_params);
 
     // Generate our hex mesh
     let (mesh, hex_coords) = generate_hex_mesh(0.3, 1.0);
     let mesh_handle = meshes.add(mesh);
-    let mut rng = rand::thread_rng();
-    for q in 0..board_params.size_y {
-
-        // Push Cell row
-        game.board.push(Vec::new());
-
-        for r in 0..board_params.size_x {
-
-            let tile = rng.gen_range(0..10);
-            let tile = if tile > 0 && tile < 5 {
-                0
-            } else if tile >= 5 && tile < 7 {
-                1
-            } else {
-                2
+    for (q, row) in game.board.iter_mut().enumerate() {
+        for (r, cell) in row.iter_mut().enumerate() {
+            let tile = match cell.terrain {
+                Terrain::Water => 0,
+                Terrain::Grass => 1,
+                Terrain::Hills => 2,
             };
             let color = board_colors.colors[tile].clone();
 
-            let height = match tile {
-                0 => 0.05,
-                1 => 0.1 + rng.gen_range(-0.05..0.05),
-                2 => 0.2 + rng.gen_range(-0.1..0.1),
-                _ => unreachable!(),
-            };
-
-            let pos = geometry::center(1.0, &hex::HexCoord::new(q as isize, r as isize), &[0., height, 0.]);
-
-            // Push individual Cells
-            game.board[q].push(Cell { height });
-
-            /* game.board = (0..BOARD_SIZE_I)            // FIXME: Board size
-                .map(|j| {
-                    (0..BOARD_SIZE_J)
-                        .map(|i| {                   
-                            Cell { height: 10.0 }
-                        })
-                        .collect()
-                })
-                .collect();
-            */
-            
-            add_hex(
-                Vec3::new(pos[0], pos[1], pos[2]),
+            let entity = add_hex(
+                tile_center(BoardPos::new(r, q), cell.height),
                 0.2,
                 color,
                 mesh_handle.clone(),
                 Arc::clone(&arc_commands),
                 &mut materials,
             ); // .lock().unwrap().insert(Cell { height: 10.0 });
+            arc_commands.lock().unwrap().entity(entity).insert(Tile(BoardPos::new(r, q)));
+            cell.entity = Some(entity);
 
-            if tile == 0 {
-                arc_commands.lock().unwrap().spawn().insert(Water);
-                // .with_child(Water);    // FIXME: This is probably haphazard. Oridinally: commands.with(Water)
+            if cell.terrain == Terrain::Water {
+                arc_commands.lock().unwrap().entity(entity).insert(Water(cell.height));
             }
         }
     }
-    systems_loaded.tiles = true;
+    setup.advance();
+}
+
+/// Where the tile of `pos` goes, at `height`. The board's rows run along the hex `q` axis.
+pub fn tile_center(pos: BoardPos, height: f32) -> Vec3 {
+    let [x, y, z] = geometry::center(TILE_RADIUS, &hex::HexCoord::new(pos.j as isize, pos.i as isize), &[0., height, 0.]);
+    Vec3::new(x, y, z)
+}
+
+/// The cells of the board `board_params` describes, with nothing spawned for them yet.
+/// The same seed always gives the same board.
+pub fn sample_board(board_params: &BoardParams) -> Vec<Vec<Cell>> {
+    let mut rng = StdRng::seed_from_u64(board_params.seed);
+    (0..board_params.size_y)
+        .map(|_| {
+            (0..board_params.size_x)
+                .map(|_| {
+                    let terrain = match rng.gen_range(0..10) {
+                        1..=4 => Terrain::Water,
+                        5..=6 => Terrain::Grass,
+                        _ => Terrain::Hills,
+                    };
+                    let height = match terrain {
+                        Terrain::Water => 0.05,
+                        Terrain::Grass => 0.1 + rng.gen_range(-0.05..0.05),
+                        Terrain::Hills => 0.2 + rng.gen_range(-0.1..0.1),
+                    };
+                    Cell { height, terrain, building: None, entity: None, stock: terrain.stock_capacity() }
+                })
+                .collect()
+        })
+        .collect()
 }
 
 /// Spawn a hex in the world
@@ -162,16 +177,52 @@ pub fn generate_hex_mesh(height: f32, radius: f32) -> (Mesh, hex::HexCoord) {
 }
 
 
-pub struct Water;
+/// A water tile, and the height it rests at on a calm sea
+pub struct Water(pub f32);
 /// Ripple water tiles slightly
-pub fn water_ripple(time: Res<Time>, mut q: Query<&mut Transform, With<Water>>) {
+pub fn water_ripple(time: Res<Time>, effects: Res<MarketEffects>, mut q: Query<(&Water, &mut Transform)>) {
     let time = time.seconds_since_startup() as f32;
-    for mut t in q.iter_mut() {
+    // the market lifts and lowers the whole sea
+    let rise = effects.water_rise();
+    for (water, mut t) in q.iter_mut() {
         let (x, z) = (t.translation.x, t.translation.z);
 
         let ripple1 = (time / 2. + (x / 3.) + (z / 3.)).sin() * 0.1 - 0.05;
         let ripple2 = (time + (x / 3.) - (z / 4.)).cos() * 0.1 - 0.05;
         let ripple3 = (time * 2. + (x / 5.) - (z / 7.)).sin() * 0.1 - 0.05;
-        t.translation = Vec3::new(x, ripple1 + ripple2 + ripple3, z);
+        t.translation = Vec3::new(x, water.0 + rise + ripple1 + ripple2 + ripple3, z);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::watch::MarketWatch;
+    use crate::config::market::{ MarketRule, MarketTarget };
+
+    fn ripple_height(effects: MarketEffects) -> f32 {
+        let mut world = World::default();
+        world.insert_resource(Time::default());
+        world.insert_resource(effects);
+        let pos = BoardPos::new(2, 3);
+        let tile = world.spawn().insert(Transform::from_translation(tile_center(pos, 0.05))).insert(Tile(pos)).insert(Water(0.05)).id();
+        SystemStage::single(water_ripple.system()).run(&mut world);
+        world.get::<Transform>(tile).unwrap().translation.y
+    }
+
+    #[test]
+    fn rising_market_lifts_the_water_tiles() {
+        let symbols = vec![String::from("HOTUSDT")];
+        let mut effects = MarketEffects::new(&[MarketRule::new("HOTUSDT", MarketTarget::WaterLevel, 0.1, 0.5, 2.0, 0.0)]);
+        let mut watch = MarketWatch::new(&symbols);
+        watch.update("HOTUSDT", 1.0, None);
+        effects.update(&watch, 0.0);
+        watch.update("HOTUSDT", 1.1, None);
+        effects.update(&watch, 0.0);
+        let rise = effects.water_rise();
+        assert!(rise > 0.0);
+
+        let calm = ripple_height(MarketEffects::default());
+        assert!((ripple_height(effects) - calm - rise).abs() < 1e-5);
+    }
+}
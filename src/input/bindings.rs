@@ -0,0 +1,435 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use bevy::input::gamepad::{ GamepadEvent, GamepadEventType };
+use serde::{ Deserialize, Serialize };
+
+use crate::game::GameState;
+use crate::game::economy::BuildingType;
+use crate::game::replay::Replay;
+use crate::game::turns::Actor;
+use crate::storage::{ self, StorageError };
+use super::{ EditorMode, GameCommand, MenuInput, MoveDirection, MoveTarget };
+
+const BINDINGS_FILE: &str = "bindings.ron";
+/// How far a stick has to be pushed to count as pressed
+const AXIS_THRESHOLD: f32 = 0.5;
+
+/// Which bindings apply, depending on what the player is doing
+#[derive(Copy, Clone, Debug, PartialEq, Hash, Serialize, Deserialize)]
+pub enum InputContext {
+    Gameplay,
+    /// Layered over `Gameplay` while editing the board
+    Editor,
+    Menu,
+}
+impl Eq for InputContext {}
+
+/// The contexts active in `state`, the first one taking precedence
+pub fn active_contexts(state: &GameState, editor: bool) -> Vec<InputContext> {
+    match state {
+        GameState::Playing if editor => vec![InputContext::Editor, InputContext::Gameplay],
+        GameState::Playing => vec![InputContext::Gameplay],
+        GameState::MainMenu
+        | GameState::Paused
+        | GameState::Settings
+        | GameState::Controls
+        | GameState::GameOver
+        | GameState::Leaderboard => vec![InputContext::Menu],
+        _ => vec![],
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Modifier {
+    Shift,
+    Ctrl,
+    Alt,
+}
+impl Eq for Modifier {}
+
+impl Modifier {
+    pub const ALL: [Modifier; 3] = [Modifier::Shift, Modifier::Ctrl, Modifier::Alt];
+
+    pub fn keys(&self) -> [KeyCode; 2] {
+        match self {
+            Modifier::Shift => [KeyCode::LShift, KeyCode::RShift],
+            Modifier::Ctrl => [KeyCode::LControl, KeyCode::RControl],
+            Modifier::Alt => [KeyCode::LAlt, KeyCode::RAlt],
+        }
+    }
+
+    pub fn is_modifier_key(key: KeyCode) -> bool {
+        Modifier::ALL.iter().any(|modifier| modifier.keys().contains(&key))
+    }
+}
+
+/// A single button, or a stick pushed one way
+#[derive(Copy, Clone, Debug, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Trigger {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    GamepadButton(GamepadButtonType),
+    GamepadAxis { axis: GamepadAxisType, positive: bool },
+}
+impl Eq for Trigger {}
+
+/// What's pressed this frame, on every device
+#[derive(Default)]
+pub struct InputSnapshot {
+    pub held: HashSet<Trigger>,
+    pub just_pressed: HashSet<Trigger>,
+    pub modifiers: HashSet<Modifier>,
+}
+
+/// A chord of triggers, plus modifiers, bound to a command in one context
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Binding {
+    pub context: InputContext,
+    pub modifiers: Vec<Modifier>,
+    /// All of these have to be held, and one of them just pressed
+    pub chord: Vec<Trigger>,
+    pub command: GameCommand,
+}
+
+impl Binding {
+    pub fn new(context: InputContext, trigger: Trigger, command: GameCommand) -> Self {
+        Binding { context, modifiers: Vec::new(), chord: vec![trigger], command }
+    }
+
+    pub fn with_modifier(mut self, modifier: Modifier) -> Self {
+        self.modifiers.push(modifier);
+        self
+    }
+
+    /// Fires when exactly its modifiers are held, so Ctrl+Z doesn't also fire Z.
+    /// Commands that repeat fire on every frame the chord is held.
+    pub fn fires(&self, input: &InputSnapshot) -> bool {
+        let modifiers: HashSet<Modifier> = self.modifiers.iter().copied().collect();
+        !self.chord.is_empty()
+            && modifiers == input.modifiers
+            && self.chord.iter().all(|trigger| input.held.contains(trigger))
+            && (self.command.repeats() || self.chord.iter().any(|trigger| input.just_pressed.contains(trigger)))
+    }
+
+    /// Whether both bindings are set off by the same input
+    pub fn same_input(&self, other: &Binding) -> bool {
+        let modifiers = |b: &Binding| b.modifiers.iter().copied().collect::<HashSet<_>>();
+        let chord = |b: &Binding| b.chord.iter().copied().collect::<HashSet<_>>();
+        modifiers(self) == modifiers(other) && chord(self) == chord(other)
+    }
+
+    /// How the input reads in the controls screen, e.g. "Ctrl + Key(Z)"
+    pub fn describe(&self) -> String {
+        let mut parts: Vec<String> = self.modifiers.iter().map(|m| format!("{:?}", m)).collect();
+        parts.extend(self.chord.iter().map(|trigger| match trigger {
+            Trigger::GamepadAxis { axis, positive } => format!("{:?}{}", axis, if *positive { "+" } else { "-" }),
+            _ => format!("{:?}", trigger),
+        }));
+        parts.join(" + ")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Bindings {
+    pub bindings: Vec<Binding>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Bindings { bindings: Bindings::defaults() }
+    }
+}
+
+impl Bindings {
+    pub fn defaults() -> Vec<Binding> {
+        use GameCommand::*;
+        use InputContext::{ Editor, Gameplay };
+        let moves = [
+            (KeyCode::Up, GamepadButtonType::DPadUp, GamepadAxisType::LeftStickY, true, MoveDirection::Up),
+            (KeyCode::Down, GamepadButtonType::DPadDown, GamepadAxisType::LeftStickY, false, MoveDirection::Down),
+            (KeyCode::Right, GamepadButtonType::DPadRight, GamepadAxisType::LeftStickX, true, MoveDirection::Right),
+            (KeyCode::Left, GamepadButtonType::DPadLeft, GamepadAxisType::LeftStickX, false, MoveDirection::Left),
+        ];
+        let mut bindings = Vec::new();
+        for (key, button, axis, positive, dir) in moves.iter() {
+            let step = Move { actor: Actor::Player, to: MoveTarget::Dir(*dir) };
+            bindings.push(Binding::new(Gameplay, Trigger::Key(*key), step.clone()));
+            bindings.push(Binding::new(Gameplay, Trigger::GamepadButton(*button), step.clone()));
+            bindings.push(Binding::new(Gameplay, Trigger::GamepadAxis { axis: *axis, positive: *positive }, step));
+        }
+        bindings.extend(vec![
+            Binding::new(Gameplay, Trigger::Key(KeyCode::H), Harvest),
+            Binding::new(Gameplay, Trigger::GamepadButton(GamepadButtonType::South), Harvest),
+            Binding::new(Gameplay, Trigger::Key(KeyCode::Key1), PlaceBuilding { kind: BuildingType::House }),
+            Binding::new(Gameplay, Trigger::Key(KeyCode::Key2), PlaceBuilding { kind: BuildingType::PizzaShop }),
+            Binding::new(Gameplay, Trigger::Key(KeyCode::Return), EndTurn),
+            Binding::new(Gameplay, Trigger::GamepadButton(GamepadButtonType::West), EndTurn),
+            Binding::new(Gameplay, Trigger::Key(KeyCode::Escape), Pause),
+            Binding::new(Gameplay, Trigger::GamepadButton(GamepadButtonType::Start), Pause),
+            Binding::new(Gameplay, Trigger::Key(KeyCode::F2), ToggleEditor),
+            Binding::new(Gameplay, Trigger::Key(KeyCode::W), PanCamera { dir: MoveDirection::Up }),
+            Binding::new(Gameplay, Trigger::Key(KeyCode::S), PanCamera { dir: MoveDirection::Down }),
+            Binding::new(Gameplay, Trigger::Key(KeyCode::A), PanCamera { dir: MoveDirection::Left }),
+            Binding::new(Gameplay, Trigger::Key(KeyCode::D), PanCamera { dir: MoveDirection::Right }),
+            Binding::new(Gameplay, Trigger::Key(KeyCode::Home), SnapCamera),
+            Binding::new(Gameplay, Trigger::Key(KeyCode::C), ToggleCameraFollow),
+            Binding::new(Gameplay, Trigger::Key(KeyCode::Q), RotateCamera { clockwise: false }),
+            Binding::new(Gameplay, Trigger::Key(KeyCode::E), RotateCamera { clockwise: true }),
+            Binding::new(Gameplay, Trigger::Key(KeyCode::P), CycleCameraProjection),
+            // Esc leaves the editor before it pauses
+            Binding::new(Editor, Trigger::Key(KeyCode::Escape), ToggleEditor),
+            Binding::new(Editor, Trigger::Key(KeyCode::Z), Undo).with_modifier(Modifier::Ctrl),
+            Binding::new(Editor, Trigger::Key(KeyCode::Y), Redo).with_modifier(Modifier::Ctrl),
+            Binding::new(Editor, Trigger::Key(KeyCode::Z), Redo).with_modifier(Modifier::Ctrl).with_modifier(Modifier::Shift),
+            Binding::new(Editor, Trigger::Key(KeyCode::F), FillSelection),
+            Binding::new(Editor, Trigger::Key(KeyCode::Delete), ClearSelection),
+            Binding::new(InputContext::Menu, Trigger::Key(KeyCode::Up), Menu(MenuInput::Up)),
+            Binding::new(InputContext::Menu, Trigger::Key(KeyCode::Down), Menu(MenuInput::Down)),
+            Binding::new(InputContext::Menu, Trigger::Key(KeyCode::Return), Menu(MenuInput::Select)),
+            Binding::new(InputContext::Menu, Trigger::Key(KeyCode::Escape), Menu(MenuInput::Back)),
+            Binding::new(InputContext::Menu, Trigger::Key(KeyCode::L), Menu(MenuInput::Leaderboard)),
+            Binding::new(InputContext::Menu, Trigger::GamepadButton(GamepadButtonType::DPadUp), Menu(MenuInput::Up)),
+            Binding::new(InputContext::Menu, Trigger::GamepadButton(GamepadButtonType::DPadDown), Menu(MenuInput::Down)),
+            Binding::new(InputContext::Menu, Trigger::GamepadButton(GamepadButtonType::South), Menu(MenuInput::Select)),
+            Binding::new(InputContext::Menu, Trigger::GamepadButton(GamepadButtonType::East), Menu(MenuInput::Back)),
+        ]);
+        bindings
+    }
+
+    /// The commands `input` fires in `contexts`. A binding in an earlier context shadows
+    /// the same input in the later ones.
+    pub fn commands(&self, contexts: &[InputContext], input: &InputSnapshot) -> Vec<GameCommand> {
+        let mut fired: Vec<&Binding> = Vec::new();
+        for context in contexts {
+            let shadowed = fired.len();
+            for binding in self.bindings.iter().filter(|b| b.context == *context && b.fires(input)) {
+                if !fired[..shadowed].iter().any(|earlier| earlier.same_input(binding)) {
+                    fired.push(binding);
+                }
+            }
+        }
+        fired.into_iter().map(|binding| binding.command.clone()).collect()
+    }
+
+    /// `saved` over the defaults: every command keeps the bindings it was saved with, and the commands
+    /// the file doesn't know about yet get their default ones, as long as nothing saved uses the same input
+    pub fn merge(saved: Bindings) -> Self {
+        let mut bindings = saved.bindings;
+        let missing: Vec<Binding> = Bindings::defaults()
+            .into_iter()
+            .filter(|default| {
+                !bindings.iter().any(|b| b.context == default.context && (b.command == default.command || b.same_input(default)))
+            })
+            .collect();
+        bindings.extend(missing);
+        Bindings { bindings }
+    }
+
+    pub fn save(&self) -> Result<(), StorageError> {
+        storage::save_ron(BINDINGS_FILE, self)
+    }
+}
+
+pub fn load_bindings(mut bindings: ResMut<Bindings>) {
+    match storage::load_ron::<Bindings>(BINDINGS_FILE) {
+        Ok(Some(saved)) => *bindings = Bindings::merge(saved),
+        Ok(None) => info!("load_bindings: no saved bindings, using the defaults"),
+        Err(e) => warn!("load_bindings: {}, using the defaults", e),
+    }
+}
+
+// collect what's pressed on the keyboard, the mouse and every connected gamepad
+pub fn update_input_snapshot(
+    mut snapshot: ResMut<InputSnapshot>,
+    keys: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    pad_buttons: Res<Input<GamepadButton>>,
+    pad_axes: Res<Axis<GamepadAxis>>,
+    mut pad_events: EventReader<GamepadEvent>,
+    mut gamepads: Local<HashSet<Gamepad>>,
+) {
+    for GamepadEvent(gamepad, event_type) in pad_events.iter() {
+        match event_type {
+            GamepadEventType::Connected => { gamepads.insert(*gamepad); }
+            GamepadEventType::Disconnected => { gamepads.remove(gamepad); }
+            _ => (),
+        }
+    }
+
+    let previously_held = std::mem::take(&mut snapshot.held);
+    let mut held: HashSet<Trigger> = HashSet::new();
+    let mut just_pressed: HashSet<Trigger> = HashSet::new();
+
+    held.extend(keys.get_pressed().map(|key| Trigger::Key(*key)));
+    just_pressed.extend(keys.get_just_pressed().map(|key| Trigger::Key(*key)));
+    held.extend(mouse.get_pressed().map(|button| Trigger::Mouse(*button)));
+    just_pressed.extend(mouse.get_just_pressed().map(|button| Trigger::Mouse(*button)));
+
+    for gamepad in gamepads.iter() {
+        held.extend(
+            pad_buttons.get_pressed().filter(|b| b.0 == *gamepad).map(|b| Trigger::GamepadButton(b.1)),
+        );
+        just_pressed.extend(
+            pad_buttons.get_just_pressed().filter(|b| b.0 == *gamepad).map(|b| Trigger::GamepadButton(b.1)),
+        );
+        for axis in [GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY, GamepadAxisType::RightStickX, GamepadAxisType::RightStickY].iter() {
+            let value = pad_axes.get(GamepadAxis(*gamepad, *axis)).unwrap_or(0.0);
+            if value.abs() > AXIS_THRESHOLD {
+                let trigger = Trigger::GamepadAxis { axis: *axis, positive: value > 0.0 };
+                held.insert(trigger);
+                // sticks have no press events, so a push counts from the frame it crosses the threshold
+                if !previously_held.contains(&trigger) {
+                    just_pressed.insert(trigger);
+                }
+            }
+        }
+    }
+
+    snapshot.modifiers = Modifier::ALL
+        .iter()
+        .copied()
+        .filter(|modifier| modifier.keys().iter().any(|key| keys.pressed(*key)))
+        .collect();
+    snapshot.held = held;
+    snapshot.just_pressed = just_pressed;
+}
+
+// Systems of a state that just became active would still see the commands sent to the previous one,
+// e.g. the Select that opened a menu would pick that menu's first entry.
+pub fn drop_stale_commands(
+    state: Res<State<GameState>>,
+    mut game_commands: ResMut<Events<GameCommand>>,
+    mut last_state: Local<Option<GameState>>,
+) {
+    // `State` is borrowed mutably by its own driver every frame, so `is_changed` can't tell a transition
+    let current = state.current().clone();
+    if last_state.as_ref() != Some(&current) {
+        game_commands.clear();
+        *last_state = Some(current);
+    }
+}
+
+// A state set within a frame runs its systems in that same frame, while the commands that set it are
+// still around. The screens that open this way start from a clean slate.
+pub fn clear_commands(mut game_commands: ResMut<Events<GameCommand>>) {
+    game_commands.clear();
+}
+
+// turn the input into commands for whatever is going on right now
+pub fn dispatch_bindings(
+    bindings: Res<Bindings>,
+    snapshot: Res<InputSnapshot>,
+    state: Res<State<GameState>>,
+    editor: Res<EditorMode>,
+    replay: Res<Replay>,
+    mut game_commands: EventWriter<GameCommand>,
+) {
+    let contexts = active_contexts(state.current(), editor.0);
+    for command in bindings.commands(&contexts, &snapshot) {
+        // a replay plays itself, the player only gets to pause it
+        if replay.playing && command.affects_game() {
+            continue;
+        }
+        game_commands.send(command);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(triggers: &[Trigger], modifiers: &[Modifier]) -> InputSnapshot {
+        InputSnapshot {
+            held: triggers.iter().copied().collect(),
+            just_pressed: triggers.iter().copied().collect(),
+            modifiers: modifiers.iter().copied().collect(),
+        }
+    }
+
+    #[test]
+    fn modifiers_have_to_match_exactly() {
+        let bindings = Bindings {
+            bindings: vec![
+                Binding::new(InputContext::Gameplay, Trigger::Key(KeyCode::Z), GameCommand::Harvest),
+                Binding::new(InputContext::Gameplay, Trigger::Key(KeyCode::Z), GameCommand::EndTurn)
+                    .with_modifier(Modifier::Ctrl),
+            ],
+        };
+        let contexts = [InputContext::Gameplay];
+        assert_eq!(vec![GameCommand::Harvest], bindings.commands(&contexts, &press(&[Trigger::Key(KeyCode::Z)], &[])));
+        assert_eq!(
+            vec![GameCommand::EndTurn],
+            bindings.commands(&contexts, &press(&[Trigger::Key(KeyCode::Z)], &[Modifier::Ctrl]))
+        );
+    }
+
+    #[test]
+    fn chords_need_every_trigger_held() {
+        let chord = vec![Trigger::Mouse(MouseButton::Left), Trigger::Key(KeyCode::Space)];
+        let binding = Binding { context: InputContext::Gameplay, modifiers: vec![], chord, command: GameCommand::Pause };
+        assert!(!binding.fires(&press(&[Trigger::Key(KeyCode::Space)], &[])));
+
+        let mut input = press(&[Trigger::Key(KeyCode::Space)], &[]);
+        input.held.insert(Trigger::Mouse(MouseButton::Left));
+        assert!(binding.fires(&input));
+    }
+
+    #[test]
+    fn earlier_contexts_shadow_later_ones() {
+        let bindings = Bindings::default();
+        let escape = press(&[Trigger::Key(KeyCode::Escape)], &[]);
+        assert_eq!(vec![GameCommand::Pause], bindings.commands(&active_contexts(&GameState::Playing, false), &escape));
+        assert_eq!(
+            vec![GameCommand::ToggleEditor],
+            bindings.commands(&active_contexts(&GameState::Playing, true), &escape)
+        );
+        assert_eq!(
+            vec![GameCommand::Menu(MenuInput::Back)],
+            bindings.commands(&active_contexts(&GameState::Paused, false), &escape)
+        );
+    }
+
+    #[test]
+    fn saved_bindings_keep_up_with_new_defaults() {
+        // saved before there was anything but harvesting, and moved onto Q since
+        let saved = Bindings { bindings: vec![Binding::new(InputContext::Gameplay, Trigger::Key(KeyCode::Q), GameCommand::Harvest)] };
+        let merged = Bindings::merge(saved);
+        let gameplay = [InputContext::Gameplay];
+
+        assert_eq!(vec![GameCommand::Harvest], merged.commands(&gameplay, &press(&[Trigger::Key(KeyCode::Q)], &[])));
+        assert!(merged.commands(&gameplay, &press(&[Trigger::Key(KeyCode::H)], &[])).is_empty());
+        assert_eq!(vec![GameCommand::SnapCamera], merged.commands(&gameplay, &press(&[Trigger::Key(KeyCode::Home)], &[])));
+        assert_eq!(
+            vec![GameCommand::Undo],
+            merged.commands(&[InputContext::Editor], &press(&[Trigger::Key(KeyCode::Z)], &[Modifier::Ctrl])),
+        );
+    }
+
+    #[test]
+    fn panning_repeats_while_held() {
+        let bindings = Bindings::default();
+        let held = InputSnapshot { held: vec![Trigger::Key(KeyCode::W), Trigger::Key(KeyCode::H)].into_iter().collect(), ..Default::default() };
+        assert_eq!(
+            vec![GameCommand::PanCamera { dir: MoveDirection::Up }],
+            bindings.commands(&[InputContext::Gameplay], &held),
+        );
+    }
+
+    #[test]
+    fn held_steps_repeat() {
+        let bindings = Bindings::default();
+        let held = InputSnapshot { held: vec![Trigger::Key(KeyCode::Up)].into_iter().collect(), ..Default::default() };
+        assert_eq!(
+            vec![GameCommand::Move { actor: Actor::Player, to: MoveTarget::Dir(MoveDirection::Up) }],
+            bindings.commands(&[InputContext::Gameplay], &held),
+        );
+    }
+
+    #[test]
+    fn bindings_roundtrip_through_ron() {
+        let bindings = Bindings::default();
+        let text = ron::ser::to_string(&bindings).unwrap();
+        assert_eq!(bindings, ron::de::from_str::<Bindings>(&text).unwrap());
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::VecDeque;
+use std::sync::{ Arc, Mutex };
+
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::game::Game;
+use crate::hextiles::{ add_hex, generate_hex_mesh };
+use super::GameCommand;
+use super::selection::{ MARKER_OFFSET, Selection, Tile, tile_translation };
+
+/// How many undo steps are kept
+pub const HISTORY_CAP: usize = 100;
+
+/// A hex placed in the editor, which can be taken away again
+pub struct PlacedHex {
+    pub at: Vec3,
+    pub color: Color,
+}
+
+/// A board edit together with the command that reverts it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Edit {
+    pub command: GameCommand,
+    pub inverse: GameCommand,
+}
+
+/// Undo and redo stacks of board edits. Every step is a group of edits, so a whole
+/// brush stroke goes away with a single undo.
+#[derive(Debug)]
+pub struct CommandHistory {
+    undo: VecDeque<Vec<Edit>>,
+    redo: Vec<Vec<Edit>>,
+    /// The brush stroke being painted, if any
+    stroke: Option<Vec<Edit>>,
+    cap: usize,
+}
+
+impl Default for CommandHistory {
+    fn default() -> Self {
+        CommandHistory::with_cap(HISTORY_CAP)
+    }
+}
+
+impl CommandHistory {
+    pub fn with_cap(cap: usize) -> Self {
+        CommandHistory { undo: VecDeque::new(), redo: Vec::new(), stroke: None, cap }
+    }
+
+    pub fn begin_stroke(&mut self) {
+        self.end_stroke();
+        self.stroke = Some(Vec::new());
+    }
+
+    pub fn end_stroke(&mut self) {
+        if let Some(stroke) = self.stroke.take() {
+            if !stroke.is_empty() {
+                self.push(stroke);
+            }
+        }
+    }
+
+    /// Remember an edit that was just made. Anything undone before can't be redone anymore.
+    pub fn record(&mut self, edit: Edit) {
+        match &mut self.stroke {
+            Some(stroke) => stroke.push(edit),
+            None => self.push(vec![edit]),
+        }
+        self.redo.clear();
+    }
+
+    fn push(&mut self, group: Vec<Edit>) {
+        self.undo.push_back(group);
+        while self.undo.len() > self.cap {
+            self.undo.pop_front();
+        }
+    }
+
+    /// The commands that revert the last step, in the order to run them
+    pub fn undo(&mut self) -> Option<Vec<GameCommand>> {
+        self.end_stroke();
+        let group = self.undo.pop_back()?;
+        let commands = group.iter().rev().map(|edit| edit.inverse.clone()).collect();
+        self.redo.push(group);
+        Some(commands)
+    }
+
+    /// The commands that make the last undone step again
+    pub fn redo(&mut self) -> Option<Vec<GameCommand>> {
+        let group = self.redo.pop()?;
+        let commands = group.iter().map(|edit| edit.command.clone()).collect();
+        self.push(group);
+        Some(commands)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty() || self.stroke.as_ref().map_or(false, |stroke| !stroke.is_empty())
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+/// Run one board edit, returning the command that reverts it, or `None` if there was nothing to do
+fn apply_board_edit(
+    command: &GameCommand,
+    commands: &Arc<Mutex<Commands>>,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+    placed: &[(Entity, Vec3, Color)],
+    removed: &mut Vec<Entity>,
+) -> Option<GameCommand> {
+    match command {
+        GameCommand::CreateHex { at, color } => {
+            let (mesh, _) = generate_hex_mesh(0.05, 0.8);
+            let hex = add_hex(*at, 0.1, *color, meshes.add(mesh), Arc::clone(commands), materials);
+            commands.lock().unwrap().entity(hex).insert(PlacedHex { at: *at, color: *color });
+            Some(GameCommand::RemoveHex { at: *at })
+        }
+        GameCommand::RemoveHex { at } => {
+            let (entity, at, color) = placed
+                .iter()
+                .find(|(entity, placed_at, _)| !removed.contains(entity) && placed_at.distance(*at) < 0.01)?;
+            commands.lock().unwrap().entity(*entity).despawn_recursive();
+            removed.push(*entity);
+            Some(GameCommand::CreateHex { at: *at, color: *color })
+        }
+        _ => None,
+    }
+}
+
+// Carry out board edits and keep their history. Holding the left mouse button paints a stroke,
+// which is undone as a whole, and so is filling the selection.
+pub fn board_edit_commands(
+    commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mouse_input: Res<Input<MouseButton>>,
+    game: Res<Game>,
+    selection: Res<Selection>,
+    mut history: ResMut<CommandHistory>,
+    mut game_commands: EventReader<GameCommand>,
+    hexes: Query<(Entity, &PlacedHex)>,
+    tiles: Query<&Transform, With<Tile>>,
+) {
+    if mouse_input.just_pressed(MouseButton::Left) {
+        history.begin_stroke();
+    }
+
+    let commands = Arc::new(Mutex::new(commands));
+    let placed: Vec<(Entity, Vec3, Color)> = hexes.iter().map(|(entity, hex)| (entity, hex.at, hex.color)).collect();
+    // hexes despawned this frame are still around until the commands are applied
+    let mut removed = Vec::new();
+
+    for command in game_commands.iter() {
+        let replay = match command {
+            GameCommand::Undo => history.undo(),
+            GameCommand::Redo => history.redo(),
+            GameCommand::FillSelection => {
+                let color = Color::rgb(rand::thread_rng().gen(), rand::thread_rng().gen(), rand::thread_rng().gen());
+                let fills: Vec<GameCommand> = selection
+                    .cells
+                    .iter()
+                    .filter_map(|pos| tile_translation(&game, &tiles, *pos))
+                    .map(|at| GameCommand::CreateHex { at: at + Vec3::Y * MARKER_OFFSET, color })
+                    .collect();
+                // the whole fill is one step
+                history.begin_stroke();
+                for fill in fills {
+                    if let Some(inverse) = apply_board_edit(&fill, &commands, &mut meshes, &mut materials, &placed, &mut removed) {
+                        history.record(Edit { command: fill, inverse });
+                    }
+                }
+                history.end_stroke();
+                continue;
+            }
+            _ => {
+                if let Some(inverse) = apply_board_edit(command, &commands, &mut meshes, &mut materials, &placed, &mut removed) {
+                    history.record(Edit { command: command.clone(), inverse });
+                }
+                continue;
+            }
+        };
+        for step in replay.unwrap_or_default() {
+            apply_board_edit(&step, &commands, &mut meshes, &mut materials, &placed, &mut removed);
+        }
+    }
+
+    if !mouse_input.pressed(MouseButton::Left) {
+        history.end_stroke();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create(x: f32) -> Edit {
+        let at = Vec3::new(x, 0.0, 0.0);
+        Edit { command: GameCommand::CreateHex { at, color: Color::WHITE }, inverse: GameCommand::RemoveHex { at } }
+    }
+
+    #[test]
+    fn undo_reverts_in_reverse_and_redo_replays() {
+        let mut history = CommandHistory::default();
+        history.record(create(1.0));
+        history.record(create(2.0));
+
+        assert_eq!(Some(vec![create(2.0).inverse]), history.undo());
+        assert!(history.can_redo());
+        assert_eq!(Some(vec![create(2.0).command]), history.redo());
+        assert!(!history.can_redo());
+
+        history.undo();
+        history.record(create(3.0));
+        assert_eq!(None, history.redo());
+    }
+
+    #[test]
+    fn strokes_are_undone_as_one() {
+        let mut history = CommandHistory::default();
+        history.begin_stroke();
+        history.record(create(1.0));
+        history.record(create(2.0));
+        history.end_stroke();
+
+        assert_eq!(Some(vec![create(2.0).inverse, create(1.0).inverse]), history.undo());
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn oldest_steps_fall_off_past_the_cap() {
+        let mut history = CommandHistory::with_cap(2);
+        for x in 0..3 {
+            history.record(create(x as f32));
+        }
+        assert!(history.undo().is_some());
+        assert!(history.undo().is_some());
+        assert_eq!(None, history.undo());
+    }
+}
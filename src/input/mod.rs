@@ -0,0 +1,360 @@
+
+use bevy::{prelude::*, input::keyboard::KeyboardInput, input::mouse::MouseButtonInput };
+use bevy_mod_picking::{HoverEvent, PickingEvent, SelectionEvent};
+use rand::Rng;
+use serde::{ Deserialize, Serialize };
+
+pub mod bindings;
+pub mod history;
+pub mod selection;
+pub mod touch;
+
+use super::{ Game };
+use crate::game::ai::Npc;
+use crate::game::board::BoardPos;
+use crate::game::bonus::{ BONUS_POINTS, take_bonus_at };
+use crate::game::economy::BuildingType;
+use crate::game::movement::{ PathMover, Route, StepCooldown };
+use crate::game::replay::SimClock;
+use crate::game::turns::{ Actor, Ruleset, TurnState };
+use selection::Selection;
+
+// use lazy_static::lazy_static;
+
+/// The four ways the player can step on the board
+#[derive(Copy, Clone, Debug, PartialEq, Hash, Serialize, Deserialize)]
+pub enum MoveDirection {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+impl Eq for MoveDirection {}
+
+impl MoveDirection {
+    /// The cell one step from `(i, j)`, if it's still on a board of `size_i` x `size_j` cells
+    pub fn step(&self, i: usize, j: usize, size_i: usize, size_j: usize) -> Option<(usize, usize)> {
+        match self {
+            MoveDirection::Up if i + 1 < size_i => Some((i + 1, j)),
+            MoveDirection::Down if i > 0 => Some((i - 1, j)),
+            MoveDirection::Right if j + 1 < size_j => Some((i, j + 1)),
+            MoveDirection::Left if j > 0 => Some((i, j - 1)),
+            _ => None,
+        }
+    }
+
+    /// Which way the character faces after the step
+    pub fn rotation(&self) -> f32 {
+        match self {
+            MoveDirection::Up => -std::f32::consts::FRAC_PI_2,
+            MoveDirection::Down => std::f32::consts::FRAC_PI_2,
+            MoveDirection::Right => std::f32::consts::PI,
+            MoveDirection::Left => 0.0,
+        }
+    }
+}
+
+/// Where a move takes an actor
+#[derive(Copy, Clone, Debug, PartialEq, Hash, Serialize, Deserialize)]
+pub enum MoveTarget {
+    /// One step the way the keys and sticks point
+    Dir(MoveDirection),
+    /// Straight onto a neighbouring cell, the way NPCs walk
+    Cell(BoardPos),
+}
+impl Eq for MoveTarget {}
+
+/// Moving around the menus
+#[derive(Copy, Clone, Debug, PartialEq, Hash, Serialize, Deserialize)]
+pub enum MenuInput {
+    Up,
+    Down,
+    Select,
+    Back,
+    /// Open the leaderboard from the game over screen, or close it again
+    Leaderboard,
+}
+impl Eq for MenuInput {}
+
+/// Everything the player can ask the game to do. Input systems only send these as events,
+/// and the handler systems carry them out, so every input device ends up on the same path.
+/// NPCs move through here as well, see `ai::npc_step`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum GameCommand {
+    CreateHex { at: Vec3, color: Color },
+    RemoveHex { at: Vec3 },
+    Undo,
+    Redo,
+    /// Step `actor` on the board, see `move_actors`
+    Move { actor: Actor, to: MoveTarget },
+    SelectedEntityMove { to: BoardPos },
+    /// Select the cells between `from` and `to`, or add them to the selection
+    Select { from: BoardPos, to: BoardPos, add: bool },
+    ClearSelection,
+    /// Put a hex on every selected cell
+    FillSelection,
+    Harvest,
+    PlaceBuilding { kind: BuildingType },
+    EndTurn,
+    Pause,
+    /// Slide the camera across the board, for as long as the binding is held
+    PanCamera { dir: MoveDirection },
+    /// Go back to following the player after panning away
+    SnapCamera,
+    ToggleCameraFollow,
+    /// Turn the camera a sixth of the way around the board
+    RotateCamera { clockwise: bool },
+    /// Switch between the perspective, isometric and dimetric views
+    CycleCameraProjection,
+    ToggleEditor,
+    Menu(MenuInput),
+}
+
+impl GameCommand {
+    /// Whether the command changes the game, as opposed to just finding the way around it or looking at it.
+    /// Those are what a replay records and feeds back.
+    pub fn affects_game(&self) -> bool {
+        !matches!(
+            self,
+            GameCommand::Pause
+                | GameCommand::PanCamera { .. }
+                | GameCommand::SnapCamera
+                | GameCommand::ToggleCameraFollow
+                | GameCommand::RotateCamera { .. }
+                | GameCommand::CycleCameraProjection
+                | GameCommand::Menu(_)
+        )
+    }
+
+    /// Whether the command is sent every frame its binding is held, rather than once when it's pressed.
+    /// A held step is taken as often as the `StepCooldown` lets the player.
+    pub fn repeats(&self) -> bool {
+        matches!(self, GameCommand::PanCamera { .. } | GameCommand::Move { actor: Actor::Player, .. })
+    }
+
+    /// Whether an NPC sent the command. NPCs decide again when a game is played back, so these aren't recorded.
+    pub fn from_npc(&self) -> bool {
+        matches!(self, GameCommand::Move { actor: Actor::Npc(_), .. })
+    }
+}
+
+/// Whether the board is being edited rather than played, see `bindings::InputContext::Editor`
+#[derive(Default)]
+pub struct EditorMode(pub bool);
+
+pub fn toggle_editor_commands(mut game_commands: EventReader<GameCommand>, mut editor: ResMut<EditorMode>) {
+    for command in game_commands.iter() {
+        if *command == GameCommand::ToggleEditor {
+            editor.0 = !editor.0;
+            info!("toggle_editor_commands: editing {}", editor.0);
+        }
+    }
+}
+
+/// This system prints out all keyboard events as they come in
+pub fn print_keyboard_event_system(mut keyboard_input_events: EventReader<KeyboardInput>) {
+    for event in keyboard_input_events.iter() {
+        info!("Keyboard event: {:?}", event);
+    }
+}
+
+/// This system prints out all keyboard events as they come in
+pub fn print_mouse_event_system(mut commands: Commands, mut mesh: ResMut<Assets<Mesh>>, mut aterials: ResMut<Assets<StandardMaterial>>, mut mouse_input_events: EventReader<MouseButtonInput>) {
+    for event in mouse_input_events.iter() {
+        if event.button == MouseButton::Left {
+            
+            // let position = event.state;
+            info!("Mouse event: {:?}", event);
+            // create_hex_by_click(position, commands, mesh, materials);
+            
+        }
+        
+    }
+}
+
+// In the editor, clicking a picked entity drops a new hex on top of it, and dragging over the board
+// paints them. Shift-clicking a placed hex takes it away again.
+pub fn picking_events(
+    editor: Res<EditorMode>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mouse_input: Res<Input<MouseButton>>,
+    query: Query<&Transform>,
+    placed: Query<&history::PlacedHex>,
+    mut events: EventReader<PickingEvent>,
+    mut game_commands: EventWriter<GameCommand>) {
+        
+        for event in events.iter() {
+        println!("picking_events(): This event happened! {:?}", event);
+ 
+        if !editor.0 {
+            continue;
+        }
+        let (e, painting) = match event {
+            PickingEvent::Selection(SelectionEvent::JustSelected(e)) => (e, false),
+            PickingEvent::Hover(HoverEvent::JustEntered(e)) if mouse_input.pressed(MouseButton::Left) => (e, true),
+            _ => continue,
+        };
+        println!("entid: {:?}", e);
+
+        let shift = keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift);
+        match placed.get(*e) {
+            Ok(hex) if shift && !painting => game_commands.send(GameCommand::RemoveHex { at: hex.at }),
+            // the brush only paints onto the board, not onto its own strokes
+            Ok(_) if painting => (),
+            _ => {
+                if let Ok(et) = query.get(*e) {
+                    let mut at = et.translation.clone();
+                    at.y += 0.2;
+                    let color = Color::rgb(rand::thread_rng().gen(), rand::thread_rng().gen(), rand::thread_rng().gen());
+                    game_commands.send(GameCommand::CreateHex { at, color });
+                }
+            }
+        }
+    }
+}
+
+/// Take one step of `actor` from `pos` towards `to`, paying for it in turn-based mode, and move `pos` along.
+/// Bumping into the edge of the board still counts as a step. Returns whether the actor got to take it.
+/// An NPC that can't take its step hands its turn on.
+pub fn step_actor(
+    game: &Game,
+    ruleset: &Ruleset,
+    turns: &mut TurnState,
+    actor: Actor,
+    pos: &mut BoardPos,
+    to: MoveTarget,
+) -> bool {
+    let (size_i, size_j) = game.board_size();
+    let next = match to {
+        MoveTarget::Dir(dir) => dir.step(pos.i, pos.j, size_i, size_j).map_or(*pos, |(i, j)| BoardPos::new(i, j)),
+        MoveTarget::Cell(cell) => cell,
+    };
+    if next.i >= size_i || next.j >= size_j {
+        return false;
+    }
+
+    // pay for the step in turn-based mode
+    let cost = game.board[next.j][next.i].terrain.movement_cost();
+    if !turns.try_spend(ruleset, actor, cost) {
+        if ruleset.is_turn_based() && actor != Actor::Player {
+            turns.end_turn();
+        }
+        return false;
+    }
+    *pos = next;
+    true
+}
+
+// move whoever sent a move command, the player and the NPCs alike, walk the player's route,
+// then let them eat the cake they're on
+pub fn move_actors(
+    mut commands: Commands,
+    mut game_commands: EventReader<GameCommand>,
+    clock: Res<SimClock>,
+    mut game: ResMut<Game>,
+    ruleset: Res<Ruleset>,
+    mut turns: ResMut<TurnState>,
+    mut cooldown: ResMut<StepCooldown>,
+    mut selection: ResMut<Selection>,
+    mut npcs: Query<(Entity, &mut Npc)>,
+    mut routes: Query<&mut Route>,
+    mut transforms: Query<&mut Transform>,
+) {
+    if clock.running {
+        cooldown.tick(clock.delta);
+    }
+    for command in game_commands.iter() {
+        let (actor, to) = match command {
+            GameCommand::Move { actor, to } => (*actor, *to),
+            _ => continue,
+        };
+        match actor {
+            Actor::Player => {
+                // a held key sends a step every frame, the market sets how many of them get through
+                if !cooldown.ready() {
+                    continue;
+                }
+                let mut pos = BoardPos::new(game.player.i, game.player.j);
+                if !step_actor(&game, &ruleset, &mut turns, actor, &mut pos, to) {
+                    continue;
+                }
+                cooldown.start();
+                game.player.i = pos.i;
+                game.player.j = pos.j;
+                // a step of its own takes the player off its route
+                if let Some(entity) = game.player.entity {
+                    commands.entity(entity).remove::<PathMover>().remove::<Route>();
+                }
+
+                // bumping into the edge still turns the character around
+                let rotation = match to {
+                    MoveTarget::Dir(dir) => Quat::from_rotation_y(dir.rotation()),
+                    MoveTarget::Cell(_) => Quat::IDENTITY,
+                };
+                if let Some(mut transform) = game.player.entity.and_then(|entity| transforms.get_mut(entity).ok()) {
+                    *transform = Transform {
+                        translation: game.cell_translation(pos),
+                        rotation,
+                        ..Default::default()
+                    };
+                }
+            }
+            Actor::Npc(entity) => {
+                if let Ok((_, mut npc)) = npcs.get_mut(entity) {
+                    if step_actor(&game, &ruleset, &mut turns, actor, &mut npc.pos, to) {
+                        if let Ok(mut transform) = transforms.get_mut(entity) {
+                            transform.translation = game.cell_translation(npc.pos);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // a cell of the route whenever the cooldown lets the player, with the selection coming along
+    if let Some(entity) = game.player.entity {
+        if let Ok(mut route) = routes.get_mut(entity) {
+            if cooldown.ready() {
+                let from = BoardPos::new(game.player.i, game.player.j);
+                let mut pos = from;
+                let stepped = match route.0.first() {
+                    Some(next) => step_actor(&game, &ruleset, &mut turns, Actor::Player, &mut pos, MoveTarget::Cell(*next)),
+                    None => false,
+                };
+                if stepped {
+                    route.0.remove(0);
+                    cooldown.start();
+                    game.player.i = pos.i;
+                    game.player.j = pos.j;
+                    if selection.cells == [from] {
+                        selection.select(vec![pos], false);
+                    }
+                    let waypoints = vec![game.cell_translation(from), game.cell_translation(pos)];
+                    commands.entity(entity).insert(PathMover { waypoints, progress: 0.0 });
+                }
+                if !stepped || route.0.is_empty() {
+                    commands.entity(entity).remove::<Route>();
+                }
+            }
+        }
+    }
+
+    // eat the cake!
+    let player = BoardPos::new(game.player.i, game.player.j);
+    if let Some(entity) = take_bonus_at(&mut game, player) {
+        game.score += BONUS_POINTS;
+        game.cake_eaten += 1;
+        commands.entity(entity).despawn_recursive();
+    }
+    for (_, mut npc) in npcs.iter_mut() {
+        if let Some(entity) = take_bonus_at(&mut game, npc.pos) {
+            npc.score += BONUS_POINTS;
+            npc.cake_eaten += 1;
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+pub fn spawn_mesh() {
+    
+}
@@ -0,0 +1,284 @@
+use bevy::prelude::*;
+use bevy_mod_picking::{ HoverEvent, PickingEvent, SelectionEvent };
+
+use crate::game::Game;
+use crate::game::board::BoardPos;
+use crate::game::movement::MoveSubject;
+use crate::game::replay::Replay;
+use crate::hextiles::generate_hex_mesh;
+use super::{ EditorMode, GameCommand };
+
+/// How far above its tile a marker floats, the same as a hex placed in the editor
+pub const MARKER_OFFSET: f32 = 0.2;
+
+/// The board cell a tile entity draws
+pub struct Tile(pub BoardPos);
+
+/// Drawn over the hovered and the selected cells
+pub struct SelectionMarker;
+
+/// The cells picked on the board, for the editor tools and `GameCommand::SelectedEntityMove` to act on
+#[derive(Default)]
+pub struct Selection {
+    /// In the order they were picked
+    pub cells: Vec<BoardPos>,
+    /// The cell under the cursor
+    pub hovered: Option<BoardPos>,
+    /// Where a drag started, while the button is still held
+    pub anchor: Option<BoardPos>,
+}
+
+impl Selection {
+    pub fn contains(&self, pos: BoardPos) -> bool {
+        self.cells.contains(&pos)
+    }
+
+    /// Select `cells`, either instead of or on top of what was selected before
+    pub fn select(&mut self, cells: impl IntoIterator<Item = BoardPos>, add: bool) {
+        if !add {
+            self.cells.clear();
+        }
+        for pos in cells {
+            if !self.contains(pos) {
+                self.cells.push(pos);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+    }
+
+    /// The cells a drag from the anchor to the hovered cell would select
+    pub fn dragged(&self, size_i: usize, size_j: usize) -> Vec<BoardPos> {
+        match (self.anchor, self.hovered) {
+            (Some(anchor), Some(hovered)) if anchor != hovered => hex_range(anchor, hovered, size_i, size_j),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Every cell of the box `from` and `to` span in hex space, that is whose cube coordinates
+/// all lie between those of the two corners
+pub fn hex_range(from: BoardPos, to: BoardPos, size_i: usize, size_j: usize) -> Vec<BoardPos> {
+    let (a, b) = (from.hex(), to.hex());
+    let within = |x: isize, a: isize, b: isize| a.min(b) <= x && x <= a.max(b);
+    let mut cells = Vec::new();
+    for j in 0..size_j {
+        for i in 0..size_i {
+            let hex = BoardPos::new(i, j).hex();
+            if within(hex.q, a.q, b.q) && within(hex.r, a.r, b.r) && within(hex.s, a.s, b.s) {
+                cells.push(BoardPos::new(i, j));
+            }
+        }
+    }
+    cells
+}
+
+/// Where the tile of `pos` is in the world, as it was spawned, see `hextiles::tile_center`
+pub fn tile_translation(game: &Game, tiles: &Query<&Transform, With<Tile>>, pos: BoardPos) -> Option<Vec3> {
+    let tile = game.board.get(pos.j)?.get(pos.i)?.entity?;
+    tiles.get(tile).ok().map(|transform| transform.translation)
+}
+
+/// What letting go of a click or tap that started on `from` asks for: moving the one selected
+/// actor or building to `to`, or selecting what lies in between
+pub fn click_command(game: &Game, selection: &Selection, from: BoardPos, to: BoardPos, add: bool) -> GameCommand {
+    let movable = match selection.cells.as_slice() {
+        [selected] => *selected != to && MoveSubject::at(game, *selected).is_some(),
+        _ => false,
+    };
+    if from == to && !add && movable {
+        GameCommand::SelectedEntityMove { to }
+    } else {
+        GameCommand::Select { from, to, add }
+    }
+}
+
+/// The button that selects: the left one, unless the editor uses it to paint
+pub fn selection_button(editor: &EditorMode) -> MouseButton {
+    if editor.0 {
+        MouseButton::Right
+    } else {
+        MouseButton::Left
+    }
+}
+
+// keep track of the tile under the cursor
+pub fn hover_tiles(mut events: EventReader<PickingEvent>, tiles: Query<&Tile>, mut selection: ResMut<Selection>) {
+    for event in events.iter() {
+        match event {
+            PickingEvent::Hover(HoverEvent::JustEntered(e)) => {
+                if let Ok(tile) = tiles.get(*e) {
+                    selection.hovered = Some(tile.0);
+                }
+            }
+            PickingEvent::Hover(HoverEvent::JustLeft(e)) => {
+                if let Ok(tile) = tiles.get(*e) {
+                    if selection.hovered == Some(tile.0) {
+                        selection.hovered = None;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+// Clicking a tile selects it, shift-click adds it, and dragging selects the range in between.
+// Clicking off the board lets go of everything. With something movable selected, clicking
+// another tile moves it there instead.
+pub fn selection_input(
+    game: Res<Game>,
+    editor: Res<EditorMode>,
+    replay: Res<Replay>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mouse_input: Res<Input<MouseButton>>,
+    mut selection: ResMut<Selection>,
+    mut game_commands: EventWriter<GameCommand>,
+) {
+    // a replay brings its own selections
+    if replay.playing {
+        return;
+    }
+    // with Alt the editor's right button orbits the camera instead
+    if editor.0 && (keyboard_input.pressed(KeyCode::LAlt) || keyboard_input.pressed(KeyCode::RAlt)) {
+        return;
+    }
+    let button = selection_button(&editor);
+    let add = keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift);
+
+    if mouse_input.just_pressed(button) {
+        selection.anchor = selection.hovered;
+        if selection.hovered.is_none() && !add {
+            game_commands.send(GameCommand::ClearSelection);
+        }
+    }
+    if mouse_input.just_released(button) {
+        if let Some(from) = selection.anchor.take() {
+            let to = selection.hovered.unwrap_or(from);
+            game_commands.send(click_command(&game, &selection, from, to, add));
+        }
+    }
+}
+
+// clicking the player or the cake selects the cell they're on
+pub fn pick_actors(
+    game: Res<Game>,
+    editor: Res<EditorMode>,
+    replay: Res<Replay>,
+    mut events: EventReader<PickingEvent>,
+    mut game_commands: EventWriter<GameCommand>,
+) {
+    for event in events.iter() {
+        let picked = match event {
+            PickingEvent::Selection(SelectionEvent::JustSelected(e)) => Some(*e),
+            _ => None,
+        };
+        // the editor paints with the same click
+        if editor.0 || replay.playing || picked.is_none() {
+            continue;
+        }
+        let at = if picked == game.player.entity {
+            BoardPos::new(game.player.i, game.player.j)
+        } else if picked == game.bonus.entity {
+            BoardPos::new(game.bonus.i, game.bonus.j)
+        } else {
+            continue;
+        };
+        game_commands.send(GameCommand::Select { from: at, to: at, add: false });
+    }
+}
+
+pub fn selection_commands(
+    game: Res<Game>,
+    mut game_commands: EventReader<GameCommand>,
+    mut selection: ResMut<Selection>,
+) {
+    let (size_i, size_j) = game.board_size();
+    for command in game_commands.iter() {
+        match command {
+            GameCommand::Select { from, to, add } => selection.select(hex_range(*from, *to, size_i, size_j), *add),
+            GameCommand::ClearSelection => selection.clear(),
+            _ => (),
+        }
+    }
+}
+
+// redraw the markers whenever the hovered or selected cells change
+pub fn selection_markers(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut marker_assets: Local<Option<(Handle<Mesh>, [Handle<StandardMaterial>; 3])>>,
+    game: Res<Game>,
+    selection: Res<Selection>,
+    tiles: Query<&Transform, With<Tile>>,
+    markers: Query<Entity, With<SelectionMarker>>,
+) {
+    if !selection.is_changed() {
+        return;
+    }
+    for marker in markers.iter() {
+        commands.entity(marker).despawn_recursive();
+    }
+
+    let (mesh, [hovered, selected, dragged]) = marker_assets
+        .get_or_insert_with(|| {
+            let (mesh, _) = generate_hex_mesh(0.02, 0.9);
+            let mut material = |color: Color| materials.add(StandardMaterial { base_color: color, unlit: true, ..Default::default() });
+            (meshes.add(mesh), [material(Color::WHITE), material(Color::GOLD), material(Color::rgb(1.0, 0.9, 0.6))])
+        })
+        .clone();
+
+    let (size_i, size_j) = game.board_size();
+    let marked = selection
+        .cells
+        .iter()
+        .map(|pos| (*pos, selected.clone()))
+        .chain(selection.dragged(size_i, size_j).into_iter().map(|pos| (pos, dragged.clone())))
+        .chain(selection.hovered.map(|pos| (pos, hovered.clone())));
+    for (pos, material) in marked {
+        if let Some(translation) = tile_translation(&game, &tiles, pos) {
+            commands.spawn_bundle(PbrBundle {
+                mesh: mesh.clone(),
+                material,
+                transform: Transform::from_translation(translation + Vec3::Y * MARKER_OFFSET),
+                ..Default::default()
+            })
+            .insert(SelectionMarker);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_spans_the_hex_box() {
+        let from = BoardPos::new(2, 2);
+        assert_eq!(vec![from], hex_range(from, from, 8, 8));
+
+        let to = BoardPos::new(2, 5);
+        let range = hex_range(from, to, 8, 8);
+        assert!(range.contains(&from) && range.contains(&to));
+        // every cell of the shortest line between the corners is in the box
+        for hex in from.hex().line_to(&to.hex()) {
+            let (col, row) = hex.to_odd_r();
+            assert!(range.contains(&BoardPos::new(row as usize, col as usize)));
+        }
+        assert_eq!(range, hex_range(to, from, 8, 8));
+    }
+
+    #[test]
+    fn shift_adds_to_the_selection() {
+        let mut selection = Selection::default();
+        selection.select(vec![BoardPos::new(0, 0)], false);
+        selection.select(vec![BoardPos::new(1, 0), BoardPos::new(0, 0)], true);
+        assert_eq!(vec![BoardPos::new(0, 0), BoardPos::new(1, 0)], selection.cells);
+
+        selection.select(vec![BoardPos::new(3, 3)], false);
+        assert_eq!(vec![BoardPos::new(3, 3)], selection.cells);
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::BTreeMap;
+use std::f32::consts::PI;
+
+use bevy::input::touch::{ TouchInput, TouchPhase };
+use bevy::prelude::*;
+use bevy::render::camera::Camera;
+use bevy::render::render_graph::base::camera::CAMERA_3D;
+
+use crate::cameras::CameraControl;
+use crate::game::Game;
+use crate::game::replay::Replay;
+use super::GameCommand;
+use super::selection::{ Selection, Tile, click_command };
+
+/// How far a finger may wander, in pixels, and still tap
+const TAP_SLOP: f32 = 12.0;
+/// How far off a tap's ray a tile centre may be, in world units, to count as tapped
+const TAP_REACH: f32 = 0.6;
+
+/// What the fingers on the screen are doing
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Gesture {
+    /// A finger touched and let go in about the same place
+    Tap(Vec2),
+    /// One finger dragged by this many pixels
+    Pan(Vec2),
+    /// Two fingers spread by this factor, below 1 when they pinch together
+    Pinch(f32),
+    /// Two fingers turned by this many radians, counterclockwise
+    Rotate(f32),
+}
+
+struct TouchPoint {
+    start: Vec2,
+    position: Vec2,
+}
+
+/// Turns raw touch events into gestures. Kept apart from the systems so it can be fed by hand.
+#[derive(Default)]
+pub struct GestureRecognizer {
+    touches: BTreeMap<u64, TouchPoint>,
+    /// Set once the fingers dragged or a second one came down, so letting go won't tap
+    moved: bool,
+}
+
+impl GestureRecognizer {
+    pub fn feed(&mut self, event: &TouchInput) -> Vec<Gesture> {
+        match event.phase {
+            TouchPhase::Started => {
+                self.touches.insert(event.id, TouchPoint { start: event.position, position: event.position });
+                if self.touches.len() > 1 {
+                    self.moved = true;
+                }
+                Vec::new()
+            }
+            TouchPhase::Moved => self.move_to(event.id, event.position),
+            TouchPhase::Ended => {
+                let mut gestures = self.move_to(event.id, event.position);
+                if let Some(point) = self.touches.remove(&event.id) {
+                    if !self.moved && self.touches.is_empty() && (point.position - point.start).length() <= TAP_SLOP {
+                        gestures.push(Gesture::Tap(point.position));
+                    }
+                }
+                self.lift();
+                gestures
+            }
+            TouchPhase::Cancelled => {
+                self.touches.remove(&event.id);
+                self.lift();
+                Vec::new()
+            }
+        }
+    }
+
+    /// The first two fingers down, the ones pinching and rotating
+    fn pair(&self) -> Option<(Vec2, Vec2)> {
+        let mut points = self.touches.values().map(|point| point.position);
+        Some((points.next()?, points.next()?))
+    }
+
+    fn move_to(&mut self, id: u64, position: Vec2) -> Vec<Gesture> {
+        let before = self.pair();
+        let point = match self.touches.get_mut(&id) {
+            Some(point) => point,
+            None => return Vec::new(),
+        };
+        let delta = position - point.position;
+        point.position = position;
+        if delta == Vec2::ZERO || (!self.moved && (position - point.start).length() <= TAP_SLOP) {
+            return Vec::new();
+        }
+        self.moved = true;
+
+        match (before, self.pair()) {
+            (None, _) => vec![Gesture::Pan(delta)],
+            (Some((a, b)), Some((c, d))) => {
+                let (from, to) = (b - a, d - c);
+                let mut angle = to.y.atan2(to.x) - from.y.atan2(from.x);
+                if angle > PI {
+                    angle -= 2.0 * PI;
+                } else if angle < -PI {
+                    angle += 2.0 * PI;
+                }
+                let mut gestures = Vec::new();
+                if from.length() > 0.0 && to.length() > 0.0 {
+                    gestures.push(Gesture::Pinch(to.length() / from.length()));
+                    gestures.push(Gesture::Rotate(angle));
+                }
+                gestures
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn lift(&mut self) {
+        if self.touches.is_empty() {
+            self.moved = false;
+        }
+    }
+}
+
+/// The ray through a point on screen, in pixels from the bottom left, as its origin and direction
+pub fn screen_ray(screen: Vec2, window_size: Vec2, projection: Mat4, camera: Mat4) -> (Vec3, Vec3) {
+    let ndc = screen / window_size * 2.0 - Vec2::ONE;
+    let ndc_to_world = camera * projection.inverse();
+    let unproject = |depth: f32| {
+        let p = ndc_to_world * Vec4::new(ndc.x, ndc.y, depth, 1.0);
+        Vec3::new(p.x, p.y, p.z) / p.w
+    };
+    let (near, far) = (unproject(0.0), unproject(1.0));
+    (near, (far - near).normalize())
+}
+
+/// Which of `points` lies closest to the ray, if any is within `reach` of it
+pub fn nearest_to_ray(origin: Vec3, direction: Vec3, points: impl Iterator<Item = Vec3>, reach: f32) -> Option<usize> {
+    points
+        .enumerate()
+        .map(|(index, point)| {
+            let along = (point - origin).dot(direction);
+            (index, (origin + direction * along - point).length())
+        })
+        .filter(|(_, distance)| *distance <= reach)
+        .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+        .map(|(index, _)| index)
+}
+
+// Tapping a tile clicks it, dragging one finger pans the camera,
+// and two fingers zoom as they pinch and orbit as they turn
+pub fn touch_gestures(
+    mut recognizer: Local<GestureRecognizer>,
+    mut touch_events: EventReader<TouchInput>,
+    game: Res<Game>,
+    selection: Res<Selection>,
+    replay: Res<Replay>,
+    windows: Res<Windows>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    tiles: Query<(&Tile, &Transform)>,
+    mut game_commands: EventWriter<GameCommand>,
+    mut camera_controls: EventWriter<CameraControl>,
+) {
+    for event in touch_events.iter() {
+        for gesture in recognizer.feed(event) {
+            match gesture {
+                Gesture::Tap(position) => {
+                    // a replay brings its own selections
+                    if replay.playing {
+                        continue;
+                    }
+                    let window = match windows.get_primary() {
+                        Some(window) => window,
+                        None => continue,
+                    };
+                    let camera = cameras.iter().find(|(camera, _)| camera.name == Some(CAMERA_3D.to_string()));
+                    if let Some((camera, transform)) = camera {
+                        let size = Vec2::new(window.width(), window.height());
+                        let (origin, direction) = screen_ray(position, size, camera.projection_matrix, transform.compute_matrix());
+                        let tiles: Vec<(&Tile, &Transform)> = tiles.iter().collect();
+                        let tapped = nearest_to_ray(origin, direction, tiles.iter().map(|(_, transform)| transform.translation), TAP_REACH);
+                        match tapped {
+                            Some(index) => {
+                                let pos = tiles[index].0 .0;
+                                game_commands.send(click_command(&game, &selection, pos, pos, false));
+                            }
+                            None => game_commands.send(GameCommand::ClearSelection),
+                        }
+                    }
+                }
+                Gesture::Pan(delta) => camera_controls.send(CameraControl::Pan(delta)),
+                // spreading the fingers brings the board closer
+                Gesture::Pinch(scale) => camera_controls.send(CameraControl::Zoom(1.0 / scale)),
+                // the board turns with the fingers, so the camera goes the other way
+                Gesture::Rotate(angle) => camera_controls.send(CameraControl::Orbit { yaw: -angle, pitch: 0.0 }),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touch(id: u64, phase: TouchPhase, x: f32, y: f32) -> TouchInput {
+        TouchInput { phase, position: Vec2::new(x, y), force: None, id }
+    }
+
+    fn feed(recognizer: &mut GestureRecognizer, events: &[TouchInput]) -> Vec<Gesture> {
+        events.iter().flat_map(|event| recognizer.feed(event)).collect()
+    }
+
+    #[test]
+    fn short_touch_taps_and_drag_pans() {
+        let mut recognizer = GestureRecognizer::default();
+        let tap = feed(&mut recognizer, &[
+            touch(1, TouchPhase::Started, 100.0, 100.0),
+            touch(1, TouchPhase::Moved, 104.0, 101.0),
+            touch(1, TouchPhase::Ended, 104.0, 101.0),
+        ]);
+        assert_eq!(vec![Gesture::Tap(Vec2::new(104.0, 101.0))], tap);
+
+        let drag = feed(&mut recognizer, &[
+            touch(2, TouchPhase::Started, 100.0, 100.0),
+            touch(2, TouchPhase::Moved, 150.0, 100.0),
+            touch(2, TouchPhase::Moved, 150.0, 80.0),
+            touch(2, TouchPhase::Ended, 150.0, 80.0),
+        ]);
+        assert_eq!(vec![Gesture::Pan(Vec2::new(50.0, 0.0)), Gesture::Pan(Vec2::new(0.0, -20.0))], drag);
+    }
+
+    #[test]
+    fn two_fingers_pinch_and_rotate() {
+        let mut recognizer = GestureRecognizer::default();
+        let spread = feed(&mut recognizer, &[
+            touch(1, TouchPhase::Started, 0.0, 0.0),
+            touch(2, TouchPhase::Started, 100.0, 0.0),
+            touch(2, TouchPhase::Moved, 200.0, 0.0),
+        ]);
+        assert_eq!(vec![Gesture::Pinch(2.0), Gesture::Rotate(0.0)], spread);
+
+        let turned = feed(&mut recognizer, &[touch(2, TouchPhase::Moved, 0.0, 200.0)]);
+        assert_eq!(Gesture::Pinch(1.0), turned[0]);
+        match turned[1] {
+            Gesture::Rotate(angle) => assert!((angle - PI / 2.0).abs() < 1e-5),
+            other => panic!("expected a rotation, got {:?}", other),
+        }
+
+        // lifting the fingers after a pinch doesn't tap
+        let lifted = feed(&mut recognizer, &[
+            touch(2, TouchPhase::Ended, 0.0, 200.0),
+            touch(1, TouchPhase::Ended, 0.0, 0.0),
+        ]);
+        assert!(lifted.is_empty());
+    }
+
+    #[test]
+    fn tap_ray_finds_the_tile_under_it() {
+        // straight down the z axis from the middle of the screen
+        let (origin, direction) = screen_ray(Vec2::new(400.0, 300.0), Vec2::new(800.0, 600.0), Mat4::IDENTITY, Mat4::IDENTITY);
+        assert_eq!(Vec3::ZERO, origin);
+        assert_eq!(Vec3::Z, direction);
+
+        let tiles = vec![Vec3::new(1.0, 0.0, 5.0), Vec3::new(0.2, 0.1, 8.0), Vec3::new(-3.0, 0.0, 2.0)];
+        assert_eq!(Some(1), nearest_to_ray(origin, direction, tiles.iter().copied(), TAP_REACH));
+        assert_eq!(None, nearest_to_ray(origin, Vec3::Y, tiles.iter().copied(), TAP_REACH));
+    }
+}
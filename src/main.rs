@@ -1,13 +1,11 @@
-#![feature(fn_traits)]
-
 use bevy::{
-    core::FixedTimestep,
     ecs::schedule::SystemSet,
     math,
     prelude::*,
     render::{camera::Camera, render_graph::base::camera::CAMERA_3D},
 };
 use bevy::diagnostic::{ FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin };
+use bevy::input::InputSystem;
 use bevy_inspector_egui::{ Inspectable, WorldInspectorPlugin };
 
 use tokio::signal::unix::{signal, SignalKind};
@@ -27,24 +25,46 @@ use bevy_mod_picking::*;
 
 mod assets;
 mod cameras;
+mod config;
 mod api;
 mod input;
 mod hextiles;
 mod game;
 mod ui;
+mod storage;
 
 use assets::{ load_assets, AssetIndex };
-use api::binance::*;
+use api::feed::{ MarketFeed, MarketTimer, PriceFetch };
+use api::watch::MarketWatch;
+use api::stream::{ TickStream, TickWindows };
+use game::market::MarketEffects;
 use game::{board::spawn_board, player};
 use game::components::{ TileType};
 use hextiles::hex::{ HexCoord };
 use ui::{ FontType, setup_ui };
 use game::board::*;
 use game::player::*;
-use input::{ KeyCommandMap, GameCommandFnMap, move_player, print_keyboard_event_system, print_mouse_event_system };
+use input::{ EditorMode, GameCommand, MenuInput, print_keyboard_event_system, print_mouse_event_system };
+use input::bindings::{ Bindings, InputSnapshot };
+use input::history::CommandHistory;
+use input::selection::Selection;
+use ui::controls::Rebinding;
 use game::{ Game, BoardParams, BoardColors, GameState, Player, Bonus, Cell };
-use game::bonus::{ spawn_bonus, rotate_bonus };
-use cameras::{ focus_camera, setup_cameras };
+use game::bonus::{ BonusTimer, rotate_bonus };
+use game::movement::StepCooldown;
+use game::ai::{ NpcSettings, spawn_npcs };
+use game::turns::{ Ruleset, TurnState };
+use game::commons::{ Commons, HarvestLedger };
+use game::economy::Inventory;
+use game::fog::FogOfWar;
+use game::account::Profiles;
+use game::scores::{ HighScores, NameEntry };
+use game::level::{ LevelSetup, check_level_assets, finish_level_setup, start_level_setup };
+use game::replay::{ GameRng, Recording, Replay, SimClock, bonus_systems, gameplay_systems };
+use ui::menu::{ MenuMaterials, MenuSelection };
+use cameras::{ CameraControl, CameraRig, focus_camera, setup_cameras };
+use config::camera::CameraConfig;
+use config::market::MarketConfig;
 
 
 
@@ -65,17 +85,14 @@ const BOARD_COLORS: [bevy::prelude::Color; 3] = [
 
 const INITIAL_BOARD_PARAMS: BoardParams = BoardParams {
     size_x: BOARD_SIZE_I,
-    size_y: BOARD_SIZE_J
+    size_y: BOARD_SIZE_J,
+    seed: 1,
 };
 
-const INITIAL_BOARD_COLORS: BoardColors = BoardColors { colors: BOARD_COLORS };
-
-#[derive(Default)]
-pub struct SystemsLoaded {
-    ui: bool,
-    player: bool,
-    tiles: bool
-}
+const INITIAL_BOARD_COLORS: BoardColors = BoardColors {
+    colors: BOARD_COLORS,
+    depleted: Color::rgb(0.694, 0.612, 0.541),    // Dry soil #B19C8A
+};
 
 #[derive(Default)]
 pub struct MeshMonkey(Handle<Mesh>);
@@ -118,23 +135,91 @@ fn main() {
         default_value = "kitsune-proxy://SYVd4CF3BdJ4DS7KwLLgeU3_DbHoZ34Y-qroZ79DOs8/kitsune-quic/h/165.22.32.11/p/5779/--"
     )]
     proxy_url: String,
+
+    #[structopt(long, default_value = "real-time", help = "the rules to play by, real-time or turn-based")]
+    ruleset: String,
+
+    #[structopt(long, help = "play back a recorded game, see `saves/last_replay.ron`")]
+    replay: Option<String>,
+
+    #[structopt(long, help = "with --replay, check the recording without opening a window")]
+    headless: bool,
 }
 
+    let opt = Opt::from_args();
+    let ruleset = Ruleset::by_name(&opt.ruleset).unwrap_or_else(|| {
+        let names: Vec<&str> = Ruleset::ALL.iter().map(|ruleset| ruleset.name).collect();
+        eprintln!("unknown ruleset {}, pick one of {}", opt.ruleset, names.join(", "));
+        std::process::exit(2);
+    });
+    let replay = match &opt.replay {
+        None => Replay::default(),
+        Some(path) => {
+            let recording = Recording::load(path).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(2);
+            });
+            if opt.headless {
+                match game::replay::verify(&recording) {
+                    Ok(outcome) => {
+                        println!("{}: ended as recorded, {:?}", path, outcome);
+                        std::process::exit(0);
+                    }
+                    Err(e) => {
+                        eprintln!("{}: {}", path, e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            Replay::play(recording)
+        }
+    };
+
     App::build()
         .insert_resource(Msaa { samples: 8 })
         .init_resource::<Game>()
         .init_resource::<AssetIndex>()
-        .init_resource::<BinanceMarket>()
-        .init_resource::<HotPrice>()
+        .init_resource::<MarketFeed>()
+        .init_resource::<MarketConfig>()
+        .init_resource::<MarketWatch>()
+        .init_resource::<PriceFetch>()
+        .init_resource::<TickStream>()
+        .init_resource::<TickWindows>()
+        .init_resource::<MarketEffects>()
         .init_resource::<MeshMonkey>()
-        .init_resource::<KeyCommandMap>()
-        .init_resource::<GameCommandFnMap>()
-        .init_resource::<SystemsLoaded>()
+        .add_event::<GameCommand>()
+        .init_resource::<Bindings>()
+        .init_resource::<InputSnapshot>()
+        .init_resource::<EditorMode>()
+        .init_resource::<CommandHistory>()
+        .init_resource::<Selection>()
+        .add_event::<CameraControl>()
+        .init_resource::<CameraRig>()
+        .init_resource::<CameraConfig>()
+        .init_resource::<Rebinding>()
+        .init_resource::<LevelSetup>()
+        .init_resource::<NpcSettings>()
+        .init_resource::<TurnState>()
+        .init_resource::<Commons>()
+        .init_resource::<HarvestLedger>()
+        .init_resource::<FogOfWar>()
+        .init_resource::<GameRng>()
+        .init_resource::<SimClock>()
+        .insert_resource(replay)
+        .init_resource::<Profiles>()
+        .init_resource::<HighScores>()
+        .init_resource::<NameEntry>()
+        .init_resource::<BonusTimer>()
+        .init_resource::<StepCooldown>()
+        .init_resource::<MarketTimer>()
+        .init_resource::<MenuSelection>()
         .insert_resource(INITIAL_BOARD_PARAMS)
         .insert_resource(INITIAL_BOARD_COLORS)
+        .insert_resource(ruleset)
 
         //
         .add_plugins(DefaultPlugins)
+        .init_resource::<MenuMaterials>()
         // .add_plugin(InspectorPlugin::<HexCoord>::new())
         .add_plugin(WorldInspectorPlugin::new())
         // .add_plugin(DefaultPickingPlugins)
@@ -152,14 +237,35 @@ fn main() {
         // .add_state(GameState::Playing)
         .add_state(GameState::Loading)
         // Beware: Need to call those two fns in the right order.        
-        // .add_startup_system(input::init_key_map.system())
-        .add_startup_system(input::init_command_map.system())
         .add_startup_system(cameras::setup_cameras.system())        
+        .add_startup_system(game::account::load_account.system())
+        .add_startup_system(game::scores::load_high_scores.system())
+        .add_startup_system(input::bindings::load_bindings.system())
+        .add_startup_system(config::camera::load_camera_config.system())
+        // every device goes through the bindings before any state's systems run
+        .add_system_to_stage(CoreStage::PreUpdate, input::bindings::update_input_snapshot.system().label("input_snapshot").after(InputSystem))
+        .add_system_to_stage(CoreStage::PreUpdate, input::bindings::drop_stale_commands.system().label("stale_commands"))
+        .add_system_to_stage(
+            CoreStage::PreUpdate,
+            input::bindings::dispatch_bindings.system().after("input_snapshot").after("stale_commands"),
+        )
+        // gameplay runs on the sim clock, which a replay drives with the recorded frame times and commands
+        .add_system_to_stage(CoreStage::PreUpdate, game::replay::advance_sim_clock.system().label("sim_clock"))
+        .add_system_to_stage(
+            CoreStage::PreUpdate,
+            game::replay::feed_replay_commands.system().after("sim_clock").after("stale_commands"),
+        )
+        .add_system_to_stage(CoreStage::PreUpdate, game::market::market_bonus_rate.system().after("sim_clock"))
+        .add_system_to_stage(CoreStage::PreUpdate, game::market::market_player_speed.system().after("sim_clock"))
+        .add_system_to_stage(CoreStage::PostUpdate, game::replay::finish_sim_tick.system())
 
         // .add_startup_system(game::setup_board.system())
         // .add_startup_system(spawn_board.system())        
         // .add_startup_system(tilemap::startup_tilemap.system())
-        // .add_startup_system(api::binance::setup_binance.system())
+        .add_startup_system(config::market::load_market_config.system().label("market_config"))
+        .add_startup_system(api::feed::setup_price_feed.system().after("market_config"))
+        .add_startup_system(api::stream::start_tick_stream.system().after("market_config"))
+        .add_startup_system(api::watch::setup_market_watch.system().after("market_config"))
         
         
         .add_system_set(SystemSet::on_enter(GameState::Loading)
@@ -171,16 +277,50 @@ fn main() {
             .with_system(assets::check_assets_loaded.system())
         )
         
-        .add_system_set(SystemSet::on_enter(GameState::FinishedLoading)
-            // .with_system(setup.system())
-            // FIXME: Systems run in parallell - do a proper switch to GameState::Playing!
-            .with_system(ui::setup_ui.system())
-            .with_system(hextiles::sample_level.system())
-            .with_system(player::spawn_player.system())
+        .add_system_set(SystemSet::on_enter(GameState::MainMenu).with_system(ui::menu::spawn_menu.system()))
+        .add_system_set(SystemSet::on_resume(GameState::MainMenu).with_system(ui::menu::spawn_menu.system()))
+        .add_system_set(SystemSet::on_update(GameState::MainMenu)
+            .with_system(ui::menu::menu_navigation.system())
+            .with_system(ui::menu::menu_display.system())
+            .with_system(game::replay::start_pending_replay.system())
+        )
+        .add_system_set(SystemSet::on_pause(GameState::MainMenu).with_system(ui::menu::despawn_menu.system()))
+        .add_system_set(SystemSet::on_exit(GameState::MainMenu).with_system(ui::menu::despawn_menu.system()))
+
+        .add_system_set(SystemSet::on_enter(GameState::Settings).with_system(ui::menu::spawn_menu.system()))
+        .add_system_set(SystemSet::on_update(GameState::Settings)
+            .with_system(ui::menu::menu_navigation.system())
+            .with_system(ui::menu::menu_display.system())
+            .with_system(ui::menu::menu_back_commands.system())
+        )
+        .add_system_set(SystemSet::on_pause(GameState::Settings).with_system(ui::menu::despawn_menu.system()))
+        .add_system_set(SystemSet::on_resume(GameState::Settings).with_system(ui::menu::spawn_menu.system()))
+        .add_system_set(SystemSet::on_exit(GameState::Settings).with_system(ui::menu::despawn_menu.system()))
+
+        .add_system_set(SystemSet::on_enter(GameState::Controls)
+            .with_system(ui::controls::spawn_controls.system())
+            .with_system(input::bindings::clear_commands.system())
+        )
+        .add_system_set(SystemSet::on_update(GameState::Controls)
+            .with_system(ui::controls::controls_commands.system())
+            .with_system(ui::controls::controls_text_system.system())
         )
+        .add_system_set(SystemSet::on_exit(GameState::Controls).with_system(ui::controls::despawn_controls.system()))
 
+        // builds the whole level, both for the first game and for every restart
+        .add_system_set(SystemSet::on_enter(GameState::FinishedLoading)
+            .with_system(reset_level.system())
+            .with_system(start_level_setup.system())
+            .with_system(game::replay::start_replay.system())
+        )
+        // one stage after the other: assets, board, entities, UI
         .add_system_set(SystemSet::on_update(GameState::FinishedLoading)
-            .with_system(loading_finished.system())
+            .with_system(check_level_assets.system().label("assets"))
+            .with_system(hextiles::sample_level.system().label("board").after("assets"))
+            .with_system(player::spawn_player.system().label("player").after("board"))
+            .with_system(spawn_npcs.system().label("npcs").after("player"))
+            .with_system(ui::setup_ui.system().label("ui").after("npcs"))
+            .with_system(finish_level_setup.system().after("ui"))
         )
 
         // .add_startup_system(hextiles::sample_level.system())
@@ -191,36 +331,95 @@ fn main() {
         
         .add_system_set(SystemSet::on_enter(GameState::Playing)
             .with_system(setup.system())
+            .with_system(game::turns::setup_turns.system())
+            .with_system(game::market::setup_market_effects.system())
         )
         
         .add_system_set(
-            SystemSet::on_update(GameState::Playing)
-                .with_system(game::player::inflate_player_by_price.system())
-                .with_system(input::mouse_button_input.system())
+            // the rules of the game, which a replay goes through the same way, see `replay::simulate`
+            gameplay_systems(SystemSet::on_update(GameState::Playing))
+                // the market plays into the game through its rules, see `game::market`
+                .with_system(game::market::update_market_effects.system().label("market_effects"))
+                .with_system(game::player::inflate_player_by_price.system().after("market_effects"))
                 .with_system(input::print_keyboard_event_system.system())
                 .with_system(input::print_mouse_event_system.system())
                 .with_system(input::picking_events.system())
-                .with_system(move_player.system())
-                .with_system(focus_camera.system())
+                .with_system(input::history::board_edit_commands.system())
+                .with_system(input::touch::touch_gestures.system().before("selection"))
+                .with_system(input::selection::hover_tiles.system().label("hover"))
+                .with_system(
+                    input::selection::selection_input.system().label("selection_input").after("hover").before("selection"),
+                )
+                .with_system(
+                    input::selection::pick_actors.system().label("pick_actors").after("selection_input").before("selection"),
+                )
+                .with_system(input::selection::selection_markers.system().after("selection").after("hover"))
+                .with_system(game::movement::animate_moves.system())
+                .with_system(game::economy::harvest_pop_system.system())
+                .with_system(game::fog::update_fog.system())
+                .with_system(game::commons::stock_tint.system())
+                .with_system(cameras::camera_mouse_input.system().label("camera_input"))
+                .with_system(cameras::camera_controls.system().label("camera_controls").after("camera_input"))
+                .with_system(cameras::switch_projection.system().after("camera_controls"))
+                .with_system(focus_camera.system().after("camera_controls"))
                 .with_system(rotate_bonus.system())
                 .with_system(ui::scoreboard_system.system())
                 .with_system(ui::price_text_system.system())                
+                .with_system(ui::turn_order_text_system.system())
+                .with_system(ui::inventory_text_system.system())
                 .with_system(hextiles::water_ripple.system())
+                .with_system(ui::menu::pause_commands.system())
+                .with_system(input::toggle_editor_commands.system())
+        )
+        .add_system_set(SystemSet::on_exit(GameState::Playing)
+            .with_system(game::replay::finish_replay.system())
+            .with_system(teardown.system())
+        )
+
+        .add_system_set(SystemSet::on_enter(GameState::Paused).with_system(ui::menu::spawn_menu.system()))
+        .add_system_set(SystemSet::on_update(GameState::Paused)
+            .with_system(ui::menu::menu_navigation.system())
+            .with_system(ui::menu::menu_display.system())
+            .with_system(ui::menu::menu_back_commands.system())
         )
-        .add_system_set(SystemSet::on_exit(GameState::Playing).with_system(teardown.system()))
+        .add_system_set(SystemSet::on_exit(GameState::Paused).with_system(ui::menu::despawn_menu.system()))
         //
         .add_system_set(
-            SystemSet::on_enter(GameState::GameOver).with_system(ui::display_score.system()),
+            SystemSet::on_enter(GameState::GameOver)
+                .with_system(ui::display_score.system())
+                .with_system(game::account::record_game_over.system())
+                .with_system(game::scores::start_name_entry.system())
         )
         .add_system_set(
-            SystemSet::on_update(GameState::GameOver).with_system(gameover_keyboard.system()),
+            SystemSet::on_update(GameState::GameOver)
+                .with_system(gameover_keyboard.system())
+                .with_system(game::scores::name_entry_keyboard.system())
+                .with_system(ui::leaderboard::name_entry_text_system.system())
         )
+        .add_system_set(SystemSet::on_resume(GameState::GameOver).with_system(input::bindings::clear_commands.system()))
         .add_system_set(SystemSet::on_exit(GameState::GameOver).with_system(teardown.system()))
+        .add_system_set(
+            SystemSet::on_enter(GameState::Leaderboard)
+                .with_system(ui::leaderboard::spawn_leaderboard.system())
+                .with_system(input::bindings::clear_commands.system())
+        )
+        .add_system_set(
+            SystemSet::on_update(GameState::Leaderboard).with_system(ui::leaderboard::leaderboard_commands.system()),
+        )
+        .add_system_set(
+            SystemSet::on_exit(GameState::Leaderboard).with_system(ui::leaderboard::despawn_leaderboard.system()),
+        )
+        // prices come in off the main thread, see `api::feed::start_price_fetch`
+        .add_system(api::feed::poll_price_fetch.system())
+        .add_system(api::stream::drain_tick_stream.system())
+        .add_system(ui::profile::toggle_profile_screen.system())
+        .add_system(ui::profile::profile_keyboard.system())
+        .add_system(ui::profile::profile_text_system.system())
+        .add_system_set(bonus_systems())
         .add_system_set(
             SystemSet::new()
-                .with_run_criteria(FixedTimestep::step(5.0))
-                .with_system(spawn_bonus.system())
-                .with_system(refresh_binance_data.system())
+                .with_run_criteria(api::feed::market_timer_finished.system())
+                .with_system(api::feed::start_price_fetch.system())
         )
         .run();
 }
@@ -234,14 +433,32 @@ pub struct PickaBundle {
     bound_volume: BoundVol
 }
 
-fn loading_finished(systems_loaded: Res<SystemsLoaded>, mut state: ResMut<State<GameState>>) {
-    
-    if systems_loaded.player && systems_loaded.ui && systems_loaded.tiles {
-        info!("loading_finished: finished");
-        state.set(GameState::Playing).unwrap();
-    } else {
-        warn!("loading_finished(): not yet - player: {:?}, ui: {:?}, tiles: {:?}", systems_loaded.player, systems_loaded.ui, systems_loaded.tiles);
-    }
+// reset the game state for a fresh level, the board and actors are rebuilt alongside
+fn reset_level(
+    mut game: ResMut<Game>,
+    mut bonus_timer: ResMut<BonusTimer>,
+    mut cooldown: ResMut<StepCooldown>,
+    mut ledger: ResMut<HarvestLedger>,
+    mut fog: ResMut<FogOfWar>,
+    mut editor: ResMut<EditorMode>,
+    mut history: ResMut<CommandHistory>,
+    mut commons: ResMut<Commons>,
+    mut selection: ResMut<Selection>,
+) {
+    game.cake_eaten = 0;
+    game.score = 0;
+    game.player.inventory = Inventory::default();
+    // the old bonus went with the teardown
+    game.bonus.entity = None;
+    bonus_timer.0.reset();
+    *cooldown = StepCooldown::default();
+    commons.timer.reset();
+    ledger.0.clear();
+    *fog = FogOfWar::default();
+    editor.0 = false;
+    // the placed hexes went with the teardown
+    *history = CommandHistory::default();
+    *selection = Selection::default();
 }
 
 fn setup(
@@ -250,14 +467,7 @@ fn setup(
         mut materials: ResMut<Assets<StandardMaterial>>,
         mut game: ResMut<Game>,
 
-        board_params: Res<BoardParams>,
         asset_index: Res<AssetIndex>) {
-    // reset the game state
-    game.cake_eaten = 0;
-    game.score = 0;
-    game.player.i = board_params.size_x / 2;
-    game.player.j = board_params.size_y / 2;
-
     commands.spawn_bundle(LightBundle {
         transform: Transform::from_xyz(4.0, 5.0, 4.0),
         light: Light {
@@ -350,20 +560,34 @@ fn teardown(mut commands: Commands, entities: Query<Entity, Without<Camera>>) {
 }
 
 
-// restart the game when pressing spacebar
-fn gameover_keyboard(mut state: ResMut<State<GameState>>, keyboard_input: Res<Input<KeyCode>>) {
-    if keyboard_input.just_pressed(KeyCode::Space) {
-        state.set(GameState::Playing).unwrap();
-    }
-}
-
-fn refresh_binance_data(
-    hot_price: ResMut<api::binance::HotPrice>,
-    binance_api: Res<BinanceMarket>,
+// replay the same map with spacebar or roll a new one with N, look at the leaderboard
+// or go back to the menu, unless a name is being typed in
+fn gameover_keyboard(
+    mut state: ResMut<State<GameState>>,
+    mut board_params: ResMut<BoardParams>,
     keyboard_input: Res<Input<KeyCode>>,
+    mut game_commands: EventReader<GameCommand>,
+    name_entry: Res<NameEntry>,
 ) {
-    api::binance::get_price(hot_price, binance_api);
-    if keyboard_input.just_pressed(KeyCode::K) {
-        println!("refresh_binance_data: K pressed: ");
+    let commands: Vec<GameCommand> = game_commands.iter().cloned().collect();
+    if name_entry.active {
+        return;
+    }
+    let next = if keyboard_input.just_pressed(KeyCode::Space) {
+        state.set(GameState::FinishedLoading)
+    } else if keyboard_input.just_pressed(KeyCode::N) {
+        board_params.seed = rand::random();
+        info!("gameover_keyboard: new map #{}", board_params.seed);
+        state.set(GameState::FinishedLoading)
+    } else if commands.contains(&GameCommand::Menu(MenuInput::Leaderboard)) {
+        state.push(GameState::Leaderboard)
+    } else if commands.contains(&GameCommand::Menu(MenuInput::Back)) {
+        state.set(GameState::MainMenu)
+    } else {
+        Ok(())
+    };
+    if let Err(e) = next {
+        warn!("gameover_keyboard: {:?}", e);
     }
 }
+
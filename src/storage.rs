@@ -0,0 +1,53 @@
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{ de::DeserializeOwned, Serialize };
+
+/// Where local save files live, relative to the working directory
+pub const SAVE_DIR: &str = "saves";
+
+#[derive(Debug)]
+pub enum StorageError {
+    Io(std::io::Error),
+    Ron(ron::Error),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StorageError::Io(e) => write!(f, "io error: {}", e),
+            StorageError::Ron(e) => write!(f, "ron error: {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for StorageError {
+    fn from(e: std::io::Error) -> Self { StorageError::Io(e) }
+}
+
+impl From<ron::Error> for StorageError {
+    fn from(e: ron::Error) -> Self { StorageError::Ron(e) }
+}
+
+pub fn save_path(name: &str) -> PathBuf {
+    PathBuf::from(SAVE_DIR).join(name)
+}
+
+/// Read `name` from the save directory. `Ok(None)` if it doesn't exist yet.
+pub fn load_ron<T: DeserializeOwned>(name: &str) -> Result<Option<T>, StorageError> {
+    let path = save_path(name);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = fs::read_to_string(path)?;
+    Ok(Some(ron::de::from_str(&contents)?))
+}
+
+/// Write `value` to `name` in the save directory, creating the directory if needed
+pub fn save_ron<T: Serialize>(name: &str, value: &T) -> Result<(), StorageError> {
+    fs::create_dir_all(SAVE_DIR)?;
+    let contents = ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default())?;
+    fs::write(save_path(name), contents)?;
+    Ok(())
+}
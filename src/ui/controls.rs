@@ -0,0 +1,161 @@
+use bevy::prelude::*;
+
+use crate::assets::AssetIndex;
+use crate::game::GameState;
+use crate::input::{ GameCommand, MenuInput };
+use crate::input::bindings::{ Bindings, InputSnapshot, Modifier, Trigger };
+use super::FontType;
+
+/// The root node of the controls screen
+pub struct ControlsScreen;
+
+pub struct ControlsText;
+
+/// The binding picked on the controls screen, and whether it's waiting for the new input
+#[derive(Default)]
+pub struct Rebinding {
+    pub selected: usize,
+    pub capturing: bool,
+}
+
+fn controls_summary(bindings: &Bindings, rebinding: &Rebinding) -> String {
+    let mut lines = vec![String::from(
+        "Controls - Up/Down to pick, Enter to rebind, Delete for the defaults, Esc to go back",
+    )];
+    for (index, binding) in bindings.bindings.iter().enumerate() {
+        let input = if index == rebinding.selected && rebinding.capturing {
+            String::from("press the new input, Esc to cancel")
+        } else {
+            binding.describe()
+        };
+        let marker = if index == rebinding.selected { ">" } else { " " };
+        lines.push(format!("{} [{:?}] {:?}: {}", marker, binding.context, binding.command, input));
+    }
+    lines.join("\n")
+}
+
+pub fn spawn_controls(
+    mut commands: Commands,
+    bindings: Res<Bindings>,
+    asset_index: Res<AssetIndex>,
+    mut rebinding: ResMut<Rebinding>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    *rebinding = Rebinding::default();
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: materials.add(Color::rgb(0.1, 0.1, 0.2).into()),
+            ..Default::default()
+        })
+        .insert(ControlsScreen)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::with_section(
+                    controls_summary(&bindings, &rebinding),
+                    TextStyle {
+                        font: asset_index.font_by_type.get(&FontType::Main).unwrap().clone(),
+                        font_size: 18.0,
+                        color: Color::rgb(0.5, 0.5, 1.0),
+                    },
+                    Default::default(),
+                ),
+                ..Default::default()
+            })
+            .insert(ControlsText);
+        });
+}
+
+// The screen is navigated with the menu commands, but a new binding is captured straight from the
+// raw input, starting the frame after the Select that asked for it
+pub fn controls_commands(
+    keyboard_input: Res<Input<KeyCode>>,
+    snapshot: Res<InputSnapshot>,
+    mut game_commands: EventReader<GameCommand>,
+    mut state: ResMut<State<GameState>>,
+    mut bindings: ResMut<Bindings>,
+    mut rebinding: ResMut<Rebinding>,
+    mut listening: Local<bool>,
+) {
+    let commands: Vec<GameCommand> = game_commands.iter().cloned().collect();
+    if rebinding.capturing {
+        if !*listening {
+            *listening = true;
+            return;
+        }
+        if keyboard_input.just_pressed(KeyCode::Escape) {
+            rebinding.capturing = false;
+            *listening = false;
+            return;
+        }
+        let is_trigger = |trigger: &&Trigger| match trigger {
+            Trigger::Key(key) => !Modifier::is_modifier_key(*key),
+            _ => true,
+        };
+        if !snapshot.just_pressed.iter().any(|trigger| is_trigger(&trigger)) {
+            return;
+        }
+        // everything held at that moment makes up the chord
+        let binding = &mut bindings.bindings[rebinding.selected];
+        binding.chord = snapshot.held.iter().filter(is_trigger).copied().collect();
+        binding.modifiers = snapshot.modifiers.iter().copied().collect();
+        rebinding.capturing = false;
+        *listening = false;
+        if let Err(e) = bindings.save() {
+            warn!("controls_commands: {}", e);
+        }
+        return;
+    }
+
+    let count = bindings.bindings.len();
+    if count == 0 {
+        return;
+    }
+    for command in commands {
+        match command {
+            GameCommand::Menu(MenuInput::Down) => rebinding.selected = (rebinding.selected + 1) % count,
+            GameCommand::Menu(MenuInput::Up) => rebinding.selected = (rebinding.selected + count - 1) % count,
+            GameCommand::Menu(MenuInput::Select) => rebinding.capturing = true,
+            GameCommand::Menu(MenuInput::Back) => {
+                if let Err(e) = state.pop() {
+                    warn!("controls_commands: {:?}", e);
+                }
+                return;
+            }
+            _ => (),
+        }
+    }
+    // Delete isn't a menu command, and it gets the defaults back however badly things got rebound
+    if keyboard_input.just_pressed(KeyCode::Delete) {
+        *bindings = Bindings::default();
+        if let Err(e) = bindings.save() {
+            warn!("controls_commands: {}", e);
+        }
+    }
+}
+
+pub fn controls_text_system(
+    bindings: Res<Bindings>,
+    rebinding: Res<Rebinding>,
+    mut query: Query<&mut Text, With<ControlsText>>,
+) {
+    if !bindings.is_changed() && !rebinding.is_changed() {
+        return;
+    }
+    for mut text in query.iter_mut() {
+        text.sections[0].value = controls_summary(&bindings, &rebinding);
+    }
+}
+
+pub fn despawn_controls(mut commands: Commands, screens: Query<Entity, With<ControlsScreen>>) {
+    for screen in screens.iter() {
+        commands.entity(screen).despawn_recursive();
+    }
+}
@@ -0,0 +1,92 @@
+use bevy::prelude::*;
+
+use crate::assets::AssetIndex;
+use crate::game::{ BoardParams, GameState };
+use crate::game::scores::{ BoardKey, HighScores, NameEntry };
+use crate::game::turns::Ruleset;
+use crate::input::{ GameCommand, MenuInput };
+use super::FontType;
+
+/// The prompt under the game over score
+pub struct NameEntryText;
+
+/// The root node of the leaderboard screen
+pub struct LeaderboardScreen;
+
+pub fn name_entry_text_system(name_entry: Res<NameEntry>, mut query: Query<&mut Text, With<NameEntryText>>) {
+    for mut text in query.iter_mut() {
+        text.sections[0].value = if name_entry.active {
+            format!("New high score! Name: {}_", name_entry.name)
+        } else {
+            String::from("Space to replay this map, N for a new map, L for the leaderboard, Esc for the menu")
+        };
+    }
+}
+
+pub fn spawn_leaderboard(
+    mut commands: Commands,
+    ruleset: Res<Ruleset>,
+    board_params: Res<BoardParams>,
+    high_scores: Res<HighScores>,
+    asset_index: Res<AssetIndex>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let key = BoardKey::new(&ruleset, &board_params);
+    let mut lines = vec![format!("Leaderboard - {} #{}", key.ruleset, key.seed)];
+    lines.extend(
+        high_scores.table
+            .top(&key)
+            .iter()
+            .enumerate()
+            .map(|(rank, entry)| format!("{}. {}  {} ({} cake)", rank + 1, entry.name, entry.score, entry.cake_eaten)),
+    );
+    if lines.len() == 1 {
+        lines.push(String::from("No scores yet"));
+    }
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: materials.add(Color::rgb(0.1, 0.1, 0.2).into()),
+            ..Default::default()
+        })
+        .insert(LeaderboardScreen)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::with_section(
+                    lines.join("\n"),
+                    TextStyle {
+                        font: asset_index.font_by_type.get(&FontType::Main).unwrap().clone(),
+                        font_size: 32.0,
+                        color: Color::rgb(0.5, 0.5, 1.0),
+                    },
+                    Default::default(),
+                ),
+                ..Default::default()
+            });
+        });
+}
+
+// back to wherever the leaderboard was opened from
+pub fn leaderboard_commands(mut state: ResMut<State<GameState>>, mut game_commands: EventReader<GameCommand>) {
+    let back = game_commands
+        .iter()
+        .any(|command| matches!(command, GameCommand::Menu(MenuInput::Back) | GameCommand::Menu(MenuInput::Leaderboard)));
+    if back {
+        if let Err(e) = state.pop() {
+            warn!("leaderboard_commands: {:?}", e);
+        }
+    }
+}
+
+pub fn despawn_leaderboard(mut commands: Commands, screens: Query<Entity, With<LeaderboardScreen>>) {
+    for screen in screens.iter() {
+        commands.entity(screen).despawn_recursive();
+    }
+}
@@ -0,0 +1,297 @@
+use bevy::app::AppExit;
+use bevy::prelude::*;
+
+use crate::assets::AssetIndex;
+use crate::game::{ BoardParams, GameState };
+use crate::game::ai::{ Difficulty, NpcSettings };
+use crate::game::level::{ LevelSetup, SetupStage };
+use crate::game::replay::{ REPLAY_FILE, Recording, Replay };
+use crate::game::turns::Ruleset;
+use crate::input::{ GameCommand, MenuInput };
+use crate::storage;
+use super::FontType;
+
+/// Board sizes to cycle through in the settings
+const BOARD_SIZES: [usize; 3] = [8, 12, 16];
+
+#[derive(Copy, Clone, Debug, PartialEq, Hash)]
+pub enum MenuAction {
+    Start,
+    WatchReplay,
+    Resume,
+    Leaderboard,
+    Settings,
+    ToMainMenu,
+    Quit,
+    ToggleRuleset,
+    CycleBoardSize,
+    CycleDifficulty,
+    Controls,
+    Back,
+}
+impl Eq for MenuAction {}
+
+/// The entries of the menu shown in `state`
+pub fn menu_actions(state: &GameState) -> Vec<MenuAction> {
+    use MenuAction::*;
+    match state {
+        GameState::MainMenu => vec![Start, WatchReplay, Leaderboard, Settings, Quit],
+        // Settings only apply to the next game, so they're only offered from the main menu
+        GameState::Paused => vec![Resume, ToMainMenu, Quit],
+        GameState::Settings => vec![ToggleRuleset, CycleBoardSize, CycleDifficulty, Controls, Back],
+        _ => vec![],
+    }
+}
+
+fn label(action: MenuAction, ruleset: &Ruleset, board_params: &BoardParams, npc_settings: &NpcSettings) -> String {
+    match action {
+        MenuAction::Start => String::from("Start"),
+        MenuAction::WatchReplay => String::from("Watch last game"),
+        MenuAction::Resume => String::from("Resume"),
+        MenuAction::Leaderboard => String::from("Leaderboard"),
+        MenuAction::Settings => String::from("Settings"),
+        MenuAction::ToMainMenu => String::from("Main menu"),
+        MenuAction::Quit => String::from("Quit"),
+        MenuAction::ToggleRuleset => format!("Rules: {}", ruleset.name),
+        MenuAction::CycleBoardSize => format!("Board: {} x {}", board_params.size_x, board_params.size_y),
+        MenuAction::CycleDifficulty => format!(
+            "Opponents: {:?}",
+            npc_settings.opponents.first().map_or(Difficulty::Normal, |(_, difficulty)| *difficulty)
+        ),
+        MenuAction::Controls => String::from("Controls"),
+        MenuAction::Back => String::from("Back"),
+    }
+}
+
+/// The root node of whichever menu is open
+pub struct MenuRoot;
+
+pub struct MenuItem {
+    pub index: usize,
+    pub action: MenuAction,
+}
+
+pub struct MenuItemText(pub MenuAction);
+
+/// The highlighted menu entry, moved with the arrow keys or the mouse
+#[derive(Default)]
+pub struct MenuSelection {
+    pub index: usize,
+}
+
+pub struct MenuMaterials {
+    pub background: Handle<ColorMaterial>,
+    pub normal: Handle<ColorMaterial>,
+    pub selected: Handle<ColorMaterial>,
+}
+
+impl FromWorld for MenuMaterials {
+    fn from_world(world: &mut World) -> Self {
+        let mut materials = world.get_resource_mut::<Assets<ColorMaterial>>().unwrap();
+        MenuMaterials {
+            background: materials.add(Color::rgba(0.1, 0.1, 0.2, 0.8).into()),
+            normal: materials.add(Color::rgb(0.15, 0.15, 0.3).into()),
+            selected: materials.add(Color::rgb(0.5, 0.5, 1.0).into()),
+        }
+    }
+}
+
+pub fn spawn_menu(
+    mut commands: Commands,
+    state: Res<State<GameState>>,
+    ruleset: Res<Ruleset>,
+    board_params: Res<BoardParams>,
+    npc_settings: Res<NpcSettings>,
+    asset_index: Res<AssetIndex>,
+    menu_materials: Res<MenuMaterials>,
+    level_setup: Res<LevelSetup>,
+    mut selection: ResMut<MenuSelection>,
+) {
+    selection.index = 0;
+    let font = asset_index.font_by_type.get(&FontType::Main).unwrap().clone();
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: menu_materials.background.clone(),
+            ..Default::default()
+        })
+        .insert(MenuRoot)
+        .with_children(|parent| {
+            for (index, action) in menu_actions(state.current()).into_iter().enumerate() {
+                parent
+                    .spawn_bundle(ButtonBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(320.0), Val::Px(56.0)),
+                            margin: Rect::all(Val::Px(6.0)),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            ..Default::default()
+                        },
+                        material: menu_materials.normal.clone(),
+                        ..Default::default()
+                    })
+                    .insert(MenuItem { index, action })
+                    .with_children(|button| {
+                        button.spawn_bundle(TextBundle {
+                            text: Text::with_section(
+                                label(action, &ruleset, &board_params, &npc_settings),
+                                TextStyle {
+                                    font: font.clone(),
+                                    font_size: 32.0,
+                                    color: Color::WHITE,
+                                },
+                                Default::default(),
+                            ),
+                            ..Default::default()
+                        })
+                        .insert(MenuItemText(action));
+                    });
+            }
+
+            // the last level couldn't be built, say why
+            if let (GameState::MainMenu, SetupStage::Failed) = (state.current(), level_setup.stage) {
+                parent.spawn_bundle(TextBundle {
+                    text: Text::with_section(
+                        format!("Could not start: {}", level_setup.error.as_deref().unwrap_or("unknown error")),
+                        TextStyle {
+                            font: font.clone(),
+                            font_size: 24.0,
+                            color: Color::rgb(1.0, 0.4, 0.4),
+                        },
+                        Default::default(),
+                    ),
+                    ..Default::default()
+                });
+            }
+        });
+}
+
+pub fn despawn_menu(mut commands: Commands, menus: Query<Entity, With<MenuRoot>>) {
+    for menu in menus.iter() {
+        commands.entity(menu).despawn_recursive();
+    }
+}
+
+/// Up and Down move the selection, Select or a click activates it
+pub fn menu_navigation(
+    mut game_commands: EventReader<GameCommand>,
+    mut selection: ResMut<MenuSelection>,
+    mut state: ResMut<State<GameState>>,
+    mut ruleset: ResMut<Ruleset>,
+    mut board_params: ResMut<BoardParams>,
+    mut npc_settings: ResMut<NpcSettings>,
+    mut replay: ResMut<Replay>,
+    mut app_exit: EventWriter<AppExit>,
+    items: Query<(&MenuItem, &Interaction), Changed<Interaction>>,
+) {
+    let count = menu_actions(state.current()).len();
+    if count == 0 {
+        return;
+    }
+    let mut activated = None;
+    for command in game_commands.iter() {
+        match command {
+            GameCommand::Menu(MenuInput::Down) => selection.index = (selection.index + 1) % count,
+            GameCommand::Menu(MenuInput::Up) => selection.index = (selection.index + count - 1) % count,
+            GameCommand::Menu(MenuInput::Select) => {
+                activated = menu_actions(state.current()).get(selection.index).copied();
+            }
+            _ => (),
+        }
+    }
+    for (item, interaction) in items.iter() {
+        match interaction {
+            Interaction::Hovered => selection.index = item.index,
+            Interaction::Clicked => activated = Some(item.action),
+            Interaction::None => (),
+        }
+    }
+    let transition = match activated {
+        Some(MenuAction::Start) => state.set(GameState::FinishedLoading),
+        Some(MenuAction::Resume) | Some(MenuAction::Back) => state.pop(),
+        Some(MenuAction::Leaderboard) => state.push(GameState::Leaderboard),
+        Some(MenuAction::Settings) => state.push(GameState::Settings),
+        Some(MenuAction::Controls) => state.push(GameState::Controls),
+        Some(MenuAction::ToMainMenu) => state.replace(GameState::MainMenu),
+        _ => Ok(()),
+    };
+    if let Err(e) = transition {
+        warn!("menu_navigation: {:?}", e);
+    }
+    match activated {
+        // `replay::start_pending_replay` takes it from here
+        Some(MenuAction::WatchReplay) => match storage::load_ron::<Recording>(REPLAY_FILE) {
+            Ok(Some(recording)) => *replay = Replay::play(recording),
+            Ok(None) => info!("menu_navigation: no game recorded yet"),
+            Err(e) => warn!("menu_navigation: {}", e),
+        },
+        Some(MenuAction::Quit) => app_exit.send(AppExit),
+        Some(MenuAction::ToggleRuleset) => {
+            let next = if ruleset.is_turn_based() { Ruleset::REAL_TIME } else { Ruleset::TURN_BASED };
+            *ruleset = next;
+        }
+        Some(MenuAction::CycleBoardSize) => {
+            let index = BOARD_SIZES.iter().position(|size| *size == board_params.size_x).unwrap_or(0);
+            let size = BOARD_SIZES[(index + 1) % BOARD_SIZES.len()];
+            board_params.size_x = size;
+            board_params.size_y = size;
+        }
+        Some(MenuAction::CycleDifficulty) => {
+            for (_, difficulty) in npc_settings.opponents.iter_mut() {
+                *difficulty = match *difficulty {
+                    Difficulty::Easy => Difficulty::Normal,
+                    Difficulty::Normal => Difficulty::Hard,
+                    Difficulty::Hard => Difficulty::Easy,
+                };
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Keep labels and highlighting in sync with the settings and the selection
+pub fn menu_display(
+    selection: Res<MenuSelection>,
+    ruleset: Res<Ruleset>,
+    board_params: Res<BoardParams>,
+    npc_settings: Res<NpcSettings>,
+    menu_materials: Res<MenuMaterials>,
+    mut items: Query<(&MenuItem, &mut Handle<ColorMaterial>)>,
+    mut texts: Query<(&MenuItemText, &mut Text)>,
+) {
+    for (item, mut material) in items.iter_mut() {
+        *material = if item.index == selection.index {
+            menu_materials.selected.clone()
+        } else {
+            menu_materials.normal.clone()
+        };
+    }
+    for (item_text, mut text) in texts.iter_mut() {
+        text.sections[0].value = label(item_text.0, &ruleset, &board_params, &npc_settings);
+    }
+}
+
+pub fn pause_commands(mut state: ResMut<State<GameState>>, mut game_commands: EventReader<GameCommand>) {
+    if game_commands.iter().any(|command| *command == GameCommand::Pause) {
+        if let Err(e) = state.push(GameState::Paused) {
+            warn!("pause_commands: {:?}", e);
+        }
+    }
+}
+
+/// Back resumes from the pause menu, and backs out of the settings
+pub fn menu_back_commands(mut state: ResMut<State<GameState>>, mut game_commands: EventReader<GameCommand>) {
+    if game_commands.iter().any(|command| *command == GameCommand::Menu(MenuInput::Back)) {
+        if let Err(e) = state.pop() {
+            warn!("menu_back_commands: {:?}", e);
+        }
+    }
+}
@@ -1,12 +1,24 @@
 use bevy::prelude::*;
 use bevy::text::*;
 
-use crate::SystemsLoaded;
+pub mod profile;
+pub mod leaderboard;
+pub mod menu;
+pub mod controls;
+
+use crate::game::level::{ LevelSetup, SetupStage };
 use crate::assets::AssetIndex;
 
 // TODO: supr:: or crate:: ?
 use super::game::Game;
-use super::api::binance::*;
+use super::game::ai::Npc;
+use super::game::turns::{ Actor, Ruleset, TurnState };
+use super::game::economy::RESOURCE_KINDS;
+use super::game::commons::HarvestLedger;
+use super::game::replay::Replay;
+use super::api::feed::{ MarketStatus, PriceFetch };
+use super::api::stream::TickStream;
+use super::api::watch::{ MarketWatch, format_price, sparkline };
 // use crate::game::Game;
 
 #[derive(Copy, Clone, Debug, PartialEq, Hash)]
@@ -17,13 +29,25 @@ pub enum FontType {
 
 impl Eq for FontType {}
 
+pub struct ScoreText;
+
+/// The market watch panel
+pub struct MarketText;
+
+pub struct TurnText;
+
+pub struct InventoryText;
+
 
 pub fn setup_ui(
     mut commands: Commands,
     mut asset_server: ResMut<AssetServer>,
     asset_index: Res<AssetIndex>,
-    mut systems_loaded: ResMut<SystemsLoaded>
+    mut setup: ResMut<LevelSetup>
 ) {
+    if !setup.is_running(SetupStage::Ui) {
+        return;
+    }
 // scoreboard
     commands.spawn_bundle(TextBundle {
     text: Text::with_section(
@@ -49,54 +73,174 @@ pub fn setup_ui(
         ..Default::default()
     },
     ..Default::default()
-});
+})
+.insert(ScoreText);
+
+// Turn order text, empty in real-time mode
+    commands.spawn_bundle(TextBundle {
+        text: Text::with_section(
+            "",
+            TextStyle {
+                font: asset_index
+                    .font_by_type
+                    .get(&FontType::Main)
+                    .unwrap()
+                    .clone(),
+                font_size: 24.0,
+                color: Color::rgb(0.5, 0.5, 1.0),
+            },
+            Default::default(),
+        ),
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                bottom: Val::Px(5.0),
+                left: Val::Px(5.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+    .insert(TurnText);
 
-// Binance price text
-    let hot_text = commands
-    .spawn_bundle(TextBundle {
+// Inventory text
+    commands.spawn_bundle(TextBundle {
+        text: Text::with_section(
+            "",
+            TextStyle {
+                font: asset_index
+                    .font_by_type
+                    .get(&FontType::Main)
+                    .unwrap()
+                    .clone(),
+                font_size: 24.0,
+                color: Color::rgb(0.5, 0.5, 1.0),
+            },
+            Default::default(),
+        ),
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Px(5.0),
+                right: Val::Px(5.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+    .insert(InventoryText);
+
+// Market watch panel
+    commands.spawn_bundle(TextBundle {
         text: Text::with_section(
-            "BTC:USDT:",
+            "Market",
             TextStyle {
                 font: asset_index
                     .font_by_type
                     .get(&FontType::Main)
                     .unwrap()
                     .clone(), // asset_server.load("fonts/FiraSans-Bold.ttf"),
-                font_size: 40.0,
+                font_size: 20.0,
                 color: Color::GOLD, // Color::rgb(0.5, 0.5, 1.0),
             },
-                Default::default(),
-            ),
-            style: Style {
-                position_type: PositionType::Absolute,
-                position: Rect {
-                    top: Val::Px(32.0),
-                    left: Val::Px(5.0),
-                    ..Default::default()
-                },
+            Default::default(),
+        ),
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                bottom: Val::Px(5.0),
+                right: Val::Px(5.0),
                 ..Default::default()
             },
             ..Default::default()
-        })
-        .insert(HotPrice {
-            last: 0.0,
-            actual: 0.0,
-        });
+        },
+        ..Default::default()
+    })
+    .insert(MarketText);
     
-    systems_loaded.ui = true;
+    setup.advance();
 }
 
 
 // update the score displayed during the game
-pub fn scoreboard_system(game: Res<Game>, mut query: Query<&mut Text, Without<HotPrice>>) {
+pub fn scoreboard_system(game: Res<Game>, npcs: Query<&Npc>, mut query: Query<&mut Text, With<ScoreText>>) {
     let mut text = query.single_mut().unwrap();
-    text.sections[0].value = format!("Sugar Rush: {}", game.score);
+    let mut value = format!("Sugar Rush: {}", game.score);
+    for npc in npcs.iter() {
+        value.push_str(&format!("\n{}: {}", npc.behaviour.name(), npc.score));
+    }
+    text.sections[0].value = value;
 }
 
-// update the score displayed during the game
-pub fn price_text_system(hot_price: Res<HotPrice>, mut query: Query<&mut Text, With<HotPrice>>) {
+// list the watched pairs, and say so when the market isn't answering
+pub fn price_text_system(
+    time: Res<Time>,
+    watch: Res<MarketWatch>,
+    fetch: Res<PriceFetch>,
+    stream: Res<TickStream>,
+    mut query: Query<&mut Text, With<MarketText>>,
+) {
     let mut text = query.single_mut().unwrap();
-    text.sections[0].value = format!("BTC:USDT: {}", hot_price.actual);
+    let status = match &fetch.status {
+        _ if stream.connected => String::from(" (live)"),
+        MarketStatus::Live => String::new(),
+        MarketStatus::Waiting => String::from(" (waiting for the market)"),
+        MarketStatus::Failing { error, retry_at } => {
+            format!(" ({}, retrying in {:.0}s)", error, (retry_at - time.seconds_since_startup()).max(0.0))
+        }
+    };
+    let mut value = format!("Market{}", status);
+    for symbol in watch.symbols.iter() {
+        let line = match watch.quote(symbol) {
+            Some(quote) => {
+                let change = quote.change_24h.map_or(String::from("--"), |change| format!("{:+.1}%", change));
+                format!("{} {} {} {}", symbol, format_price(quote.price), change, sparkline(&quote.history))
+            }
+            None => format!("{} --", symbol),
+        };
+        value.push('\n');
+        value.push_str(&line);
+    }
+    text.sections[0].value = value;
+}
+
+// show whose move it is in turn-based mode
+pub fn turn_order_text_system(
+    ruleset: Res<Ruleset>,
+    turns: Res<TurnState>,
+    npcs: Query<&Npc>,
+    mut query: Query<&mut Text, With<TurnText>>,
+) {
+    let mut text = query.single_mut().unwrap();
+    if !ruleset.is_turn_based() {
+        text.sections[0].value.clear();
+        return;
+    }
+    let names: Vec<String> = turns.order.iter().enumerate().map(|(index, actor)| {
+        let name = match actor {
+            Actor::Player => "You",
+            Actor::Npc(entity) => npcs.get(*entity).map_or("?", |npc| npc.behaviour.name()),
+        };
+        if index == turns.current {
+            format!("[{} {} AP]", name, turns.action_points)
+        } else {
+            name.to_string()
+        }
+    }).collect();
+    text.sections[0].value = format!("Turn {}: {}", turns.turn, names.join(" > "));
+}
+
+// list what the player has gathered
+pub fn inventory_text_system(game: Res<Game>, ledger: Res<HarvestLedger>, mut query: Query<&mut Text, With<InventoryText>>) {
+    let mut text = query.single_mut().unwrap();
+    let counts: Vec<String> = RESOURCE_KINDS
+        .iter()
+        .map(|kind| format!("{:?}: {}", kind, game.player.inventory.count(*kind)))
+        .collect();
+    let record = ledger.record(Actor::Player);
+    text.sections[0].value = format!("{}\nOverharvested: {:.0} of {:.0}", counts.join("  "), record.overharvested, record.harvested);
 }
 
 // display the number of cake eaten before losing
@@ -104,12 +248,20 @@ pub fn display_score(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     game: Res<Game>,
+    replay: Res<Replay>,
     mut materials: ResMut<Assets<ColorMaterial>>,
 ) {
+    let verdict = match &replay.verdict {
+        Some(Ok(())) if replay.playing => String::from("Replay ended as recorded"),
+        Some(Err(e)) if replay.playing => format!("Replay diverged: {}", e),
+        _ => String::new(),
+    };
+
     commands
         .spawn_bundle(NodeBundle {
             style: Style {
                 margin: Rect::all(Val::Auto),
+                flex_direction: FlexDirection::ColumnReverse,
                 justify_content: JustifyContent::Center,
                 align_items: AlignItems::Center,
                 ..Default::default()
@@ -130,5 +282,30 @@ pub fn display_score(
                 ),
                 ..Default::default()
             });
+            parent.spawn_bundle(TextBundle {
+                text: Text::with_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                        font_size: 40.0,
+                        color: Color::GOLD,
+                    },
+                    Default::default(),
+                ),
+                ..Default::default()
+            })
+            .insert(leaderboard::NameEntryText);
+            parent.spawn_bundle(TextBundle {
+                text: Text::with_section(
+                    verdict,
+                    TextStyle {
+                        font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                        font_size: 20.0,
+                        color: Color::rgb(0.5, 0.5, 1.0),
+                    },
+                    Default::default(),
+                ),
+                ..Default::default()
+            });
         });
 }
@@ -0,0 +1,98 @@
+use bevy::prelude::*;
+
+use crate::assets::AssetIndex;
+use crate::game::account::{ Account, Profiles };
+use super::FontType;
+
+/// The root node of the profile screen
+pub struct ProfileScreen;
+
+pub struct ProfileText;
+
+fn profile_summary(account: &Account) -> String {
+    let scores: Vec<String> = account.high_scores.iter().map(|s| s.to_string()).collect();
+    format!(
+        "{}\nAvatar: {:?} (Tab to change)\nGames played: {}\nBest score: {}\nCake eaten: {}\nHigh scores: {}",
+        account.nickname,
+        account.avatar,
+        account.stats.games_played,
+        account.stats.best_score,
+        account.cake_eaten_total,
+        scores.join(", "),
+    )
+}
+
+// open and close the profile screen with F1
+pub fn toggle_profile_screen(
+    mut commands: Commands,
+    keyboard_input: Res<Input<KeyCode>>,
+    profiles: Res<Profiles>,
+    asset_index: Res<AssetIndex>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    screens: Query<Entity, With<ProfileScreen>>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::F1) {
+        return;
+    }
+    if let Some(screen) = screens.iter().next() {
+        commands.entity(screen).despawn_recursive();
+        return;
+    }
+    let font = match asset_index.font_by_type.get(&FontType::Main) {
+        Some(font) => font.clone(),
+        None => return,
+    };
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                margin: Rect::all(Val::Auto),
+                padding: Rect::all(Val::Px(20.0)),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: materials.add(Color::rgba(0.1, 0.1, 0.2, 0.8).into()),
+            ..Default::default()
+        })
+        .insert(ProfileScreen)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::with_section(
+                    profile_summary(&profiles.account),
+                    TextStyle {
+                        font,
+                        font_size: 32.0,
+                        color: Color::rgb(0.5, 0.5, 1.0),
+                    },
+                    Default::default(),
+                ),
+                ..Default::default()
+            })
+            .insert(ProfileText);
+        });
+}
+
+// cycle through the avatars while the profile screen is open
+pub fn profile_keyboard(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut profiles: ResMut<Profiles>,
+    screens: Query<&ProfileScreen>,
+) {
+    if screens.iter().next().is_none() || !keyboard_input.just_pressed(KeyCode::Tab) {
+        return;
+    }
+    profiles.account.avatar = profiles.account.avatar.next();
+    if let Err(e) = profiles.save() {
+        warn!("profile_keyboard: {}", e);
+    }
+}
+
+pub fn profile_text_system(profiles: Res<Profiles>, mut query: Query<&mut Text, With<ProfileText>>) {
+    if !profiles.is_changed() {
+        return;
+    }
+    for mut text in query.iter_mut() {
+        text.sections[0].value = profile_summary(&profiles.account);
+    }
+}